@@ -0,0 +1,75 @@
+//! Compares the ways to evaluate one formula many times with different variable values.
+//! Run with `cargo bench`.
+
+use std::cell::Cell;
+use std::hint::black_box;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use lexper::{Error, Expression, Interpreter, Lexer, Parser, Program, Statement, VariableResolver};
+
+const ITERATIONS: u32 = 200_000;
+
+const FORMULAS: [&str; 3] = [
+    "x^2 + 3 * x - 1",
+    "(x > 0 ? sin(x) : cos(x)) * max(x, 2) / (1 + x * x)",
+    "let y = x * x in y * y + 2 * y + x - 7 % (x + 1)",
+];
+
+fn parse(raw: &str) -> Expression {
+    let mut lexer = Lexer::new(raw.to_string());
+    lexer.lex().unwrap();
+    Parser::new(lexer.tokens()).parse().unwrap()
+}
+
+/// Binds `x` once, every iteration only changes the value behind it
+struct Input(Rc<Cell<f64>>);
+
+impl VariableResolver for Input {
+    fn resolve(&self, name: &str) -> Option<Result<f64, Error>> {
+        (name == "x").then(|| Ok(self.0.get()))
+    }
+}
+
+fn time(name: &str, mut f: impl FnMut(f64) -> f64) -> Duration {
+    let start = Instant::now();
+    for i in 0..ITERATIONS {
+        black_box(f(black_box(i as f64 * 0.001)));
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "  {name:<14} {:>8.1} ns/iter",
+        elapsed.as_nanos() as f64 / ITERATIONS as f64
+    );
+    elapsed
+}
+
+fn main() {
+    for formula in FORMULAS {
+        println!("{formula}");
+        let expr = parse(formula);
+
+        let input = Rc::new(Cell::new(0.0));
+        let mut tree = Interpreter::new();
+        tree.set_variable_resolver(Input(Rc::clone(&input)));
+        let program = Program {
+            statements: vec![Statement::Expression(expr.clone())],
+        };
+        let tree_walking = time("tree walking", |x| {
+            input.set(x);
+            tree.run(&program).unwrap()
+        });
+
+        let interpreter = Interpreter::new();
+        let compiled = interpreter.compile(&expr, &["x"]).unwrap();
+        time("compiled tree", |x| compiled.eval(&[x]).unwrap());
+
+        let bytecode = interpreter.compile_bytecode(&expr, &["x"]).unwrap();
+        let vm = time("bytecode", |x| bytecode.eval(&[x]).unwrap());
+
+        println!(
+            "  bytecode is {:.1}x faster than tree walking",
+            tree_walking.as_secs_f64() / vm.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use std::fmt;
+
+use crate::{Error, lexer::Span};
+
+/// Human readable report of an [`Error`], shows the line of the source the error points to and
+/// underlines the exact part of it.
+///
+/// ## Example usage
+/// ```
+/// use lexper::Diagnostic;
+///
+/// let source = "2 + foo(1)";
+/// let error = lexper::eval(source).unwrap_err();
+/// let rendered = Diagnostic::new(&error, source).to_string();
+/// assert!(rendered.contains("2 + foo(1)"));
+/// assert!(rendered.contains("    ^^^^^^"));
+/// ```
+///
+/// The output looks like this:
+/// ```text
+/// error: Undefined identifier met: foo
+///  --> 1:5
+///   |
+/// 1 | 2 + foo(1)
+///   |     ^^^^^^
+///   = help: check the spelling or define it before using
+/// ```
+#[derive(Debug, Clone)]
+pub struct Diagnostic<'a> {
+    source: &'a str,
+    message: String,
+    span: Option<Span>,
+    help: Option<String>,
+}
+
+impl<'a> Diagnostic<'a> {
+    /// Creates a diagnostic for the error, `source` must be the string the error came from
+    pub fn new(error: &Error, source: &'a str) -> Self {
+        Self {
+            source,
+            message: error.to_string(),
+            span: error.span(),
+            help: default_help(error).map(|h| h.to_string()),
+        }
+    }
+
+    /// Replaces the help note shown under the snippet
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// The main message of the diagnostic
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The part of the source this diagnostic points to
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    /// The help note, if there is one
+    pub fn help(&self) -> Option<&str> {
+        self.help.as_deref()
+    }
+}
+
+fn default_help(error: &Error) -> Option<&'static str> {
+    match error {
+        Error::UnknownChar { .. } => {
+            Some("only numbers, identifiers, operators, parentheses and commas are allowed")
+        }
+        Error::UnexpectedValue { found: None, .. } => Some("the expression ended too early"),
+        Error::UndefinedIdentifier { .. } => Some("check the spelling or define it before using"),
+        Error::UnclosedParen { .. } => Some("add a matching ')'"),
+        Error::UnmatchedParen { .. } => Some("remove it or add a matching '(' before it"),
+        Error::NestingTooDeep { .. } => Some("split the expression or remove extra parentheses"),
+        Error::StepLimitExceeded { .. } => Some("check for recursion which never stops"),
+        Error::NoDerivative { .. } => Some("attach a rule with `Interpreter::set_derivative`"),
+        _ => None,
+    }
+}
+
+/// Moves the offset back until it lands on a char boundary of the source
+fn floor_boundary(source: &str, mut offset: usize) -> usize {
+    offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+impl fmt::Display for Diagnostic<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "error: {}", self.message)?;
+
+        if let Some(span) = self.span {
+            let start = floor_boundary(self.source, span.start);
+            let end = floor_boundary(self.source, span.end).max(start);
+
+            let line_start = self.source[..start].rfind('\n').map_or(0, |i| i + 1);
+            let line_end = self.source[start..]
+                .find('\n')
+                .map_or(self.source.len(), |i| start + i);
+            let line = self.source[line_start..line_end].trim_end_matches('\r');
+
+            let line_no = self.source[..start].matches('\n').count() + 1;
+            let col = self.source[line_start..start].chars().count() + 1;
+            let gutter = " ".repeat(line_no.to_string().len());
+
+            // tabs are kept as is, so the underline stays aligned with the snippet
+            let padding: String = self.source[line_start..start]
+                .chars()
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let underlined = self.source[start..end.min(line_end)].chars().count();
+
+            writeln!(f, "{gutter}--> {line_no}:{col}")?;
+            writeln!(f, "{gutter} |")?;
+            writeln!(f, "{line_no} | {line}")?;
+            writeln!(f, "{gutter} | {padding}{}", "^".repeat(underlined.max(1)))?;
+        }
+
+        if let Some(help) = &self.help {
+            writeln!(f, "  = help: {help}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_diagnostic_render() {
+        let source = "1 +\n  max(2, )";
+        let error = crate::eval(source).unwrap_err();
+        let rendered = Diagnostic::new(&error, source).to_string();
+        println!("{}", rendered);
+
+        assert_eq!(
+            rendered,
+            "error: Expected: 'number or (', but got: ')'\n \
+             --> 2:10\n  \
+              |\n\
+             2 |   max(2, )\n  \
+              |          ^\n"
+        );
+    }
+
+    #[test]
+    fn test_diagnostic_end_of_input() {
+        let source = "2 *";
+        let error = crate::eval(source).unwrap_err();
+        let diagnostic = Diagnostic::new(&error, source).with_help("finish the expression");
+        let rendered = diagnostic.to_string();
+        println!("{}", rendered);
+
+        assert!(rendered.contains("1 | 2 *\n  |    ^\n"));
+        assert!(rendered.ends_with("  = help: finish the expression\n"));
+    }
+
+    #[test]
+    fn test_diagnostic_number_error() {
+        let source = "2 + 1e";
+        let parse_error = "1e".parse::<f64>().unwrap_err();
+        let error = Error::parse_num_error(parse_error, Span::new(4, 6));
+        let rendered = Diagnostic::new(&error, source).to_string();
+
+        assert!(rendered.contains("1 | 2 + 1e\n  |     ^^\n"));
+    }
+}
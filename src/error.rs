@@ -3,74 +3,267 @@
 
 use std::{error, num::ParseFloatError};
 
-use crate::lexer::OperatorType;
+use crate::lexer::Span;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug)]
 pub enum Error {
     IoError(std::io::Error),
-    ParseNumError(ParseFloatError),
-    InvalidUnaryOperator(OperatorType),
+    /// A number literal which doesn't parse as `f64`, the span points to the literal
+    ParseNumError {
+        source: ParseFloatError,
+        span: Span,
+    },
+    MalformedLiteral {
+        message: String,
+        span: Span,
+    },
     UnexpectedValue {
         found: Option<String>,
         expected: String,
+        span: Span,
+    },
+    UndefinedIdentifier {
+        name: String,
+        span: Span,
+    },
+    /// `(` which is never closed, the span points to the parenthesis
+    UnclosedParen {
+        span: Span,
+    },
+    /// `)` without a matching `(`, the span points to the parenthesis
+    UnmatchedParen {
+        span: Span,
     },
-    UndefinedIdentifier(String),
     UnknownChar {
         row: usize,
         col: usize,
         char: char,
+        span: Span,
+    },
+    /// Value is outside of the domain of an operation, like `(-1)!`
+    DomainError {
+        message: String,
+        span: Span,
+    },
+    /// A function was called with a wrong number of arguments
+    ArityMismatch {
+        callee: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    /// An error returned by a function while it was called from an expression
+    CallFailed {
+        callee: String,
+        source: Box<Error>,
+        span: Span,
+    },
+    /// [`Expression::derivative`][`crate::Expression::derivative`] met a function or an
+    /// operator it doesn't know the derivative of
+    NoDerivative {
+        callee: String,
+        span: Span,
+    },
+    /// The source is longer than [`Limits::max_input_len`][`crate::Limits::max_input_len`],
+    /// the span covers the part past the limit
+    InputTooLong {
+        limit: usize,
+        span: Span,
+    },
+    /// The source has more tokens than [`Limits::max_tokens`][`crate::Limits::max_tokens`],
+    /// the span points to the first token past the limit
+    TooManyTokens {
+        limit: usize,
+        span: Span,
+    },
+    /// Expressions or calls nest deeper than [`Limits::max_depth`][`crate::Limits::max_depth`]
+    NestingTooDeep {
+        limit: usize,
+        span: Span,
+    },
+    /// The parsed expressions have more nodes than
+    /// [`Limits::max_nodes`][`crate::Limits::max_nodes`]
+    TooManyNodes {
+        limit: usize,
+        span: Span,
+    },
+    /// Evaluation took more steps than [`Limits::max_steps`][`crate::Limits::max_steps`], the
+    /// span points to the expression evaluated when it ran out
+    StepLimitExceeded {
+        limit: usize,
+        span: Span,
     },
     Other(String),
 }
 
 impl Error {
-    pub fn invalid_unary_op(op: &OperatorType) -> Self {
-        Self::InvalidUnaryOperator(op.to_owned())
+    pub fn malformed_literal(message: String, span: Span) -> Self {
+        Self::MalformedLiteral { message, span }
     }
 
-    pub fn unexpected_value(expected: &str, found: Option<&str>) -> Self {
+    pub fn parse_num_error(source: ParseFloatError, span: Span) -> Self {
+        Self::ParseNumError { source, span }
+    }
+
+    pub fn unexpected_value(expected: &str, found: Option<&str>, span: Span) -> Self {
         Self::UnexpectedValue {
             found: found.map(|x| x.to_string()),
             expected: expected.to_string(),
+            span,
         }
     }
 
-    pub fn unknown_char(row: usize, col: usize, char: char) -> Self {
-        Self::UnknownChar { row, col, char }
+    pub fn unknown_char(row: usize, col: usize, char: char, span: Span) -> Self {
+        Self::UnknownChar {
+            row,
+            col,
+            char,
+            span,
+        }
+    }
+
+    pub fn undefined(ident: String, span: Span) -> Self {
+        Self::UndefinedIdentifier { name: ident, span }
+    }
+
+    pub fn unclosed_paren(span: Span) -> Self {
+        Self::UnclosedParen { span }
     }
 
-    pub fn undefined(ident: String) -> Self {
-        Self::UndefinedIdentifier(ident)
+    pub fn unmatched_paren(span: Span) -> Self {
+        Self::UnmatchedParen { span }
+    }
+
+    pub fn domain_error(message: String, span: Span) -> Self {
+        Self::DomainError { message, span }
+    }
+
+    pub fn arity_mismatch(callee: String, expected: usize, found: usize, span: Span) -> Self {
+        Self::ArityMismatch {
+            callee,
+            expected,
+            found,
+            span,
+        }
+    }
+
+    pub fn call_failed(callee: String, source: Error, span: Span) -> Self {
+        Self::CallFailed {
+            callee,
+            source: Box::new(source),
+            span,
+        }
+    }
+
+    pub fn no_derivative(callee: String, span: Span) -> Self {
+        Self::NoDerivative { callee, span }
+    }
+
+    pub fn input_too_long(limit: usize, span: Span) -> Self {
+        Self::InputTooLong { limit, span }
+    }
+
+    pub fn too_many_tokens(limit: usize, span: Span) -> Self {
+        Self::TooManyTokens { limit, span }
+    }
+
+    pub fn nesting_too_deep(limit: usize, span: Span) -> Self {
+        Self::NestingTooDeep { limit, span }
+    }
+
+    pub fn too_many_nodes(limit: usize, span: Span) -> Self {
+        Self::TooManyNodes { limit, span }
+    }
+
+    pub fn step_limit_exceeded(limit: usize, span: Span) -> Self {
+        Self::StepLimitExceeded { limit, span }
     }
 
     pub fn other(s: String) -> Self {
         Self::Other(s)
     }
+
+    /// Returns the part of the source this error points to, if it's known
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::MalformedLiteral { span, .. }
+            | Self::UnexpectedValue { span, .. }
+            | Self::UndefinedIdentifier { span, .. }
+            | Self::UnclosedParen { span }
+            | Self::UnmatchedParen { span }
+            | Self::DomainError { span, .. }
+            | Self::ArityMismatch { span, .. }
+            | Self::UnknownChar { span, .. }
+            | Self::CallFailed { span, .. }
+            | Self::NoDerivative { span, .. }
+            | Self::InputTooLong { span, .. }
+            | Self::TooManyTokens { span, .. }
+            | Self::NestingTooDeep { span, .. }
+            | Self::TooManyNodes { span, .. }
+            | Self::StepLimitExceeded { span, .. }
+            | Self::ParseNumError { span, .. } => Some(*span),
+            Self::IoError(_) | Self::Other(_) => None,
+        }
+    }
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::IoError(e) => write!(f, "{}", e),
-            Self::InvalidUnaryOperator(op) => {
-                write!(f, "Invalid unary operator usage. Operator: {:?}", op)
-            }
-            Self::UnexpectedValue { found, expected } => {
+            Self::MalformedLiteral { message, .. } => write!(f, "Malformed literal: {}", message),
+            Self::UnexpectedValue {
+                found, expected, ..
+            } => {
                 if let Some(found) = found {
                     write!(f, "Expected: '{}', but got: '{}'", expected, found)
                 } else {
                     write!(f, "Expected: '{}', but nothing found", expected)
                 }
             }
-            Self::ParseNumError(e) => write!(f, "{}", e),
-            Self::UndefinedIdentifier(i) => write!(f, "Undefined identifier met: {}", i),
-            Self::UnknownChar { row, col, char } => write!(
+            Self::ParseNumError { source, .. } => write!(f, "{}", source),
+            Self::UndefinedIdentifier { name, .. } => {
+                write!(f, "Undefined identifier met: {}", name)
+            }
+            Self::UnclosedParen { .. } => write!(f, "Unclosed parenthesis"),
+            Self::UnmatchedParen { .. } => write!(f, "Unmatched closing parenthesis"),
+            Self::UnknownChar { row, col, char, .. } => write!(
                 f,
                 "Unknown character met at: Row: {}, Column: {}, Character: {}",
                 row, col, char
             ),
+            Self::DomainError { message, .. } => write!(f, "{}", message),
+            Self::ArityMismatch {
+                callee,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "Function '{}' expects {} arguments, but got {}",
+                callee, expected, found
+            ),
+            Self::CallFailed { callee, source, .. } => {
+                write!(f, "Call to '{}' failed: {}", callee, source)
+            }
+            Self::NoDerivative { callee, .. } => {
+                write!(f, "Derivative of '{}' is unknown", callee)
+            }
+            Self::InputTooLong { limit, .. } => {
+                write!(f, "Input is longer than {} bytes", limit)
+            }
+            Self::TooManyTokens { limit, .. } => write!(f, "Input has more than {} tokens", limit),
+            Self::NestingTooDeep { limit, .. } => {
+                write!(f, "Nesting is deeper than {} levels", limit)
+            }
+            Self::TooManyNodes { limit, .. } => {
+                write!(f, "Expression has more than {} nodes", limit)
+            }
+            Self::StepLimitExceeded { limit, .. } => {
+                write!(f, "Evaluation took more than {} steps", limit)
+            }
             Self::Other(s) => write!(f, "{s}"),
         }
     }
@@ -80,6 +273,8 @@ impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
             Self::IoError(e) => Some(e),
+            Self::ParseNumError { source, .. } => Some(source),
+            Self::CallFailed { source, .. } => Some(source.as_ref()),
             _ => None,
         }
     }
@@ -96,8 +291,10 @@ impl From<std::io::Error> for Error {
     }
 }
 
+/// Kept for code converting with `?`, the error has an empty span then, use
+/// [`Error::parse_num_error`] to point it at the literal
 impl From<ParseFloatError> for Error {
     fn from(value: ParseFloatError) -> Self {
-        Self::ParseNumError(value)
+        Self::parse_num_error(value, Span::default())
     }
 }
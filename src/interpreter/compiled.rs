@@ -0,0 +1,444 @@
+use crate::{
+    Error, Expression, Result,
+    lexer::{OperatorType, Span},
+    math,
+    parser::{PostfixOp, UnaryOp},
+    resolver::FunctionResolver,
+};
+
+use super::{
+    Callee, Function, Interpreter, LazyArgs, LazyFn, LexpFn, UserFn, call_error, with_args,
+};
+
+/// An expression prepared for evaluating it many times with different variable values.
+/// Variables are resolved to slots of the slice passed to [`eval`][`CompiledExpr::eval`] and
+/// functions to the functions themselves, so evaluation does no lookups by name and no
+/// allocations, as long as calls have at most 8 arguments.
+///
+/// Created with [`Interpreter::compile`], see it for the details.
+pub struct CompiledExpr<'i> {
+    interpreter: &'i Interpreter,
+    variables: Vec<String>,
+    root: Node<'i>,
+    /// Most nodes don't keep their spans, the step limit points at the whole expression
+    span: Span,
+}
+
+impl<'i> CompiledExpr<'i> {
+    /// Evaluates the expression, `vars` are the values of the variables in the order they were
+    /// given to [`Interpreter::compile`]
+    pub fn eval(&self, vars: &[f64]) -> Result<f64> {
+        if vars.len() != self.variables.len() {
+            return Err(Error::other(format!(
+                "Wrong number of variables, expected {}, but got {}",
+                self.variables.len(),
+                vars.len()
+            )));
+        }
+
+        self.interpreter.reset_steps();
+        self.root.eval(&Frame {
+            interpreter: self.interpreter,
+            vars,
+            locals: None,
+            span: self.span,
+        })
+    }
+
+    /// Names of the variables, in the order their values are expected by
+    /// [`eval`][`CompiledExpr::eval`]
+    pub fn variables(&self) -> impl Iterator<Item = &str> {
+        self.variables.iter().map(String::as_str)
+    }
+}
+
+impl Interpreter {
+    /// Compiles the expression for evaluating it many times, see [`CompiledExpr`]. `vars` are
+    /// the names of the variables whose values are passed to every
+    /// [`eval`][`CompiledExpr::eval`], they shadow the variables of the interpreter.
+    ///
+    /// Other variables defined on the interpreter are inlined as constants, the interpreter is
+    /// borrowed by the compiled expression, so they can't change. Variables and functions of
+    /// resolvers are still looked up by name on every evaluation. Identifiers the interpreter
+    /// doesn't know and wrong numbers of arguments to user-defined functions are reported right
+    /// away, even in branches which would never be evaluated. Bodies of user-defined functions
+    /// are evaluated by walking the tree, like with [`evaluate`][`Interpreter::evaluate`].
+    ///
+    /// ## Example usage
+    /// ```
+    /// use lexper::{Interpreter, Lexer, Parser};
+    ///
+    /// let mut lexer = Lexer::new("x^2 + y * PI".to_string());
+    /// lexer.lex().unwrap();
+    /// let expr = Parser::new(lexer.tokens()).parse().unwrap();
+    ///
+    /// let interpreter = Interpreter::new();
+    /// let compiled = interpreter.compile(&expr, &["x", "y"]).unwrap();
+    /// assert_eq!(compiled.eval(&[3.0, 0.0]).unwrap(), 9.0);
+    /// assert_eq!(compiled.eval(&[1.0, 1.0]).unwrap(), 1.0 + std::f64::consts::PI);
+    /// ```
+    pub fn compile(&self, expr: &Expression, vars: &[&str]) -> Result<CompiledExpr<'_>> {
+        let mut compiler = Compiler {
+            interpreter: self,
+            vars,
+            lets: Vec::new(),
+        };
+        let root = compiler.compile(expr)?;
+
+        Ok(CompiledExpr {
+            interpreter: self,
+            variables: vars.iter().map(|var| var.to_string()).collect(),
+            root,
+            span: expr.span(),
+        })
+    }
+
+    /// Tells where a compiled expression gets a variable of the interpreter from, in the same
+    /// order as [`get_var`][`Interpreter::get_var`] looks it up, a resolver may shadow
+    /// variables of the parents
+    pub(crate) fn var_source(&self, name: &str) -> Option<VarSource> {
+        let mut interpreter = Some(self);
+        while let Some(current) = interpreter {
+            if let Some(value) = current.globals.get(name) {
+                return Some(VarSource::Constant(*value));
+            }
+            if current.var_resolver.is_some() {
+                return Some(VarSource::Resolver);
+            }
+            interpreter = current.parent.as_deref();
+        }
+        None
+    }
+}
+
+/// See [`Interpreter::var_source`]
+pub(crate) enum VarSource {
+    /// Defined with `define_var`, can't change while the interpreter is borrowed
+    Constant(f64),
+    /// Has to be asked for by name on every evaluation
+    Resolver,
+}
+
+/// What's visible to a compiled node while it's evaluated
+pub(crate) struct Frame<'a> {
+    interpreter: &'a Interpreter,
+    vars: &'a [f64],
+    locals: Option<&'a Local<'a>>,
+    span: Span,
+}
+
+/// A value bound by `let`, the innermost binding is the first one
+struct Local<'a> {
+    value: f64,
+    parent: Option<&'a Local<'a>>,
+}
+
+pub(crate) enum Node<'i> {
+    Constant(f64),
+    /// Index into the values passed to [`CompiledExpr::eval`]
+    Slot(usize),
+    /// A `let` binding, `0` is the innermost one
+    Local(usize),
+    /// A variable provided by a resolver
+    Resolved {
+        name: String,
+        span: Span,
+    },
+
+    Unary {
+        op: UnaryOp,
+        expr: Box<Node<'i>>,
+    },
+    Postfix {
+        op: PostfixOp,
+        expr: Box<Node<'i>>,
+        span: Span,
+    },
+    Binary {
+        left: Box<Node<'i>>,
+        op: OperatorType,
+        right: Box<Node<'i>>,
+    },
+    Conditional {
+        condition: Box<Node<'i>>,
+        then: Box<Node<'i>>,
+        otherwise: Box<Node<'i>>,
+    },
+    Let {
+        value: Box<Node<'i>>,
+        body: Box<Node<'i>>,
+    },
+
+    Call {
+        callee: String,
+        target: Target<'i>,
+        args: Vec<Node<'i>>,
+        span: Span,
+    },
+}
+
+/// The function a compiled call goes to
+pub(crate) enum Target<'i> {
+    Native(&'i LexpFn),
+    /// Lazy functions get the source of the arguments as well
+    Lazy(&'i LazyFn, Vec<Expression>),
+    User(&'i UserFn),
+    Resolved(&'i dyn FunctionResolver),
+}
+
+struct Compiler<'i, 'v> {
+    interpreter: &'i Interpreter,
+    vars: &'v [&'v str],
+    lets: Vec<String>,
+}
+
+impl<'i> Compiler<'i, '_> {
+    fn compile(&mut self, expr: &Expression) -> Result<Node<'i>> {
+        let node = match expr {
+            Expression::Number(n, _) => Node::Constant(*n),
+            Expression::Variable(name, span) => self.variable(name, *span)?,
+            Expression::Unary { op, expr, .. } => Node::Unary {
+                op: *op,
+                expr: Box::new(self.compile(expr)?),
+            },
+            Expression::Postfix { op, expr, span } => Node::Postfix {
+                op: *op,
+                expr: Box::new(self.compile(expr)?),
+                span: *span,
+            },
+            Expression::Binary {
+                left, op, right, ..
+            } => Node::Binary {
+                left: Box::new(self.compile(left)?),
+                op: op.clone(),
+                right: Box::new(self.compile(right)?),
+            },
+            Expression::Conditional {
+                condition,
+                then,
+                otherwise,
+                ..
+            } => Node::Conditional {
+                condition: Box::new(self.compile(condition)?),
+                then: Box::new(self.compile(then)?),
+                otherwise: Box::new(self.compile(otherwise)?),
+            },
+            Expression::Let {
+                name, value, body, ..
+            } => {
+                let value = self.compile(value)?;
+                self.lets.push(name.clone());
+                let body = self.compile(body);
+                self.lets.pop();
+                Node::Let {
+                    value: Box::new(value),
+                    body: Box::new(body?),
+                }
+            }
+            Expression::Call { callee, args, span } => {
+                let target = match self.interpreter.get_callee(callee, args.len(), *span)? {
+                    Callee::Function(Function::Native(function)) => Target::Native(function),
+                    Callee::Function(Function::Lazy(function)) => {
+                        Target::Lazy(function, args.clone())
+                    }
+                    Callee::Function(Function::User(function)) => Target::User(function),
+                    Callee::Resolver(resolver) => Target::Resolved(resolver),
+                };
+
+                let args = args
+                    .iter()
+                    .map(|arg| self.compile(arg))
+                    .collect::<Result<Vec<_>>>()?;
+                Node::Call {
+                    callee: callee.clone(),
+                    target,
+                    args,
+                    span: *span,
+                }
+            }
+        };
+
+        Ok(node)
+    }
+
+    fn variable(&self, name: &str, span: Span) -> Result<Node<'i>> {
+        if let Some(depth) = self.lets.iter().rev().position(|local| local == name) {
+            return Ok(Node::Local(depth));
+        }
+        if let Some(slot) = self.vars.iter().position(|var| *var == name) {
+            return Ok(Node::Slot(slot));
+        }
+
+        match self.interpreter.var_source(name) {
+            Some(VarSource::Constant(value)) => Ok(Node::Constant(value)),
+            Some(VarSource::Resolver) => Ok(Node::Resolved {
+                name: name.to_string(),
+                span,
+            }),
+            None => Err(Error::undefined(name.to_string(), span)),
+        }
+    }
+}
+
+impl Node<'_> {
+    pub(crate) fn eval(&self, frame: &Frame) -> Result<f64> {
+        frame.interpreter.step(frame.span)?;
+        match self {
+            Self::Constant(n) => Ok(*n),
+            Self::Slot(slot) => Ok(frame.vars[*slot]),
+            Self::Local(depth) => {
+                let mut local = frame.locals;
+                for _ in 0..*depth {
+                    local = local.and_then(|l| l.parent);
+                }
+                // the compiler counts bindings exactly like they're pushed here, a miss is a bug
+                local
+                    .map(|l| l.value)
+                    .ok_or_else(|| Error::other(format!("`let` binding {depth} is not bound")))
+            }
+            Self::Resolved { name, span } => frame
+                .interpreter
+                .get_var(name)
+                .unwrap_or_else(|| Err(Error::undefined(name.to_owned(), *span))),
+            Self::Unary { op, expr } => Ok(Expression::apply_unary_op(op, expr.eval(frame)?)),
+            Self::Postfix { op, expr, span } => {
+                Expression::apply_postfix_op(op, expr.eval(frame)?, *span)
+            }
+            Self::Binary { left, op, right } => {
+                let left = left.eval(frame)?;
+
+                match op {
+                    OperatorType::AND if !math::is_truthy(left) => return Ok(0.0),
+                    OperatorType::OR if math::is_truthy(left) => return Ok(1.0),
+                    _ => {}
+                }
+
+                let right = right.eval(frame)?;
+                Ok(Expression::apply_binary_op(op, left, right))
+            }
+            Self::Conditional {
+                condition,
+                then,
+                otherwise,
+            } => {
+                if math::is_truthy(condition.eval(frame)?) {
+                    then.eval(frame)
+                } else {
+                    otherwise.eval(frame)
+                }
+            }
+            Self::Let { value, body } => {
+                let local = Local {
+                    value: value.eval(frame)?,
+                    parent: frame.locals,
+                };
+                body.eval(&Frame {
+                    interpreter: frame.interpreter,
+                    vars: frame.vars,
+                    locals: Some(&local),
+                    span: frame.span,
+                })
+            }
+            Self::Call {
+                callee,
+                target,
+                args,
+                span,
+            } => {
+                let result = match target {
+                    Target::Native(function) => {
+                        with_args(args, |arg| arg.eval(frame), |values| function(values))
+                    }
+                    Target::Lazy(function, exprs) => {
+                        function(&LazyArgs::compiled(exprs, args, frame))
+                    }
+                    Target::User(function) => with_args(
+                        args,
+                        |arg| arg.eval(frame),
+                        |values| frame.interpreter.call_user(function, values, *span),
+                    ),
+                    Target::Resolved(resolver) => with_args(
+                        args,
+                        |arg| arg.eval(frame),
+                        |values| resolver.call(callee, values),
+                    ),
+                };
+
+                result.map_err(call_error(callee, *span))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn compile<'i>(
+        interpreter: &'i Interpreter,
+        raw: &str,
+        vars: &[&str],
+    ) -> Result<CompiledExpr<'i>> {
+        let mut lexer = crate::Lexer::new(raw.to_string());
+        lexer.lex()?;
+        let expr = crate::Parser::new(lexer.tokens()).parse()?;
+        interpreter.compile(&expr, vars)
+    }
+
+    #[test]
+    fn test_compiled_matches_tree_walking() {
+        let mut interpreter = Interpreter::new();
+        interpreter.define_var("k".to_string(), 10.0);
+        interpreter.define_user_fn(
+            "sq".to_string(),
+            vec!["a".to_string()],
+            Expression::Binary {
+                left: Box::new(Expression::Variable("a".to_string(), Span::default())),
+                op: OperatorType::POWER,
+                right: Box::new(Expression::Number(2.0, Span::default())),
+                span: Span::default(),
+            },
+        );
+
+        let sources = [
+            "x^2 + y * PI - k",
+            "-x! + max(x, y) % 3",
+            "x > y ? sq(x) : sq(y)",
+            "let z = x * 2 in let x = z + 1 in x * z + y",
+            "if(x, 1 / x, 0) && !y",
+            "x || y / 0",
+        ];
+        for source in sources {
+            let compiled = compile(&interpreter, source, &["x", "y"]).unwrap();
+            for (x, y) in [(3.0, 4.0), (0.0, -1.5), (5.0, 2.0)] {
+                let mut lexer =
+                    crate::Lexer::new(format!("let x = {x} in let y = {y} in {source}"));
+                lexer.lex().unwrap();
+                let expr = crate::Parser::new(lexer.tokens()).parse().unwrap();
+                let expected = interpreter.evaluate(expr);
+                let actual = compiled.eval(&[x, y]);
+                match (expected, actual) {
+                    (Ok(e), Ok(a)) => assert!(e == a || e.is_nan() && a.is_nan(), "{source}"),
+                    (Err(_), Err(_)) => {}
+                    (e, a) => panic!("{source} with {x}, {y}: {e:?} != {a:?}"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let interpreter = Interpreter::new();
+        let err = compile(&interpreter, "x + y", &["x"]).err().unwrap();
+        assert!(matches!(err, Error::UndefinedIdentifier { ref name, .. } if name == "y"));
+        assert!(compile(&interpreter, "foo(x)", &["x"]).is_err());
+
+        let compiled = compile(&interpreter, "x / 2", &["x"]).unwrap();
+        assert!(compiled.eval(&[1.0, 2.0]).is_err());
+        assert_eq!(compiled.variables().collect::<Vec<_>>(), ["x"]);
+
+        let compiled = compile(&interpreter, "(x - 1)!", &["x"]).unwrap();
+        let err = compiled.eval(&[0.0]).unwrap_err();
+        assert!(matches!(err, Error::DomainError { .. }));
+    }
+}
@@ -0,0 +1,605 @@
+use crate::{
+    Error, Expression, Result,
+    lexer::{OperatorType, Span},
+    math,
+    parser::{PostfixOp, UnaryOp, Visitor, visit_node},
+};
+
+use super::{Callee, Function, Interpreter, LazyArgs, LazyFn, Scope, call_error};
+
+/// A function returning the partial derivatives of a native function with respect to each of
+/// its arguments, at the given arguments, see [`Interpreter::set_derivative`]
+pub type DerivativeFn = Box<dyn Fn(&[f64]) -> Result<Vec<f64>>>;
+
+/// A value together with its partial derivatives, the result of
+/// [`Interpreter::gradient`]. `partials[i]` is the derivative with respect to the `i`th
+/// variable passed to it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Dual {
+    pub value: f64,
+    pub partials: Vec<f64>,
+}
+
+impl Dual {
+    /// A value which doesn't depend on any variable, its partials are all zero and
+    /// aren't stored at all
+    fn constant(value: f64) -> Self {
+        Self {
+            value,
+            partials: Vec::new(),
+        }
+    }
+
+    fn is_constant(&self) -> bool {
+        self.partials.iter().all(|d| *d == 0.0)
+    }
+
+    /// A value changing like the sum of the given duals scaled by their factors, that's the
+    /// chain rule. Zero partials are skipped, so an infinite factor doesn't turn them into NaN.
+    fn chain(value: f64, terms: &[(f64, &Dual)]) -> Self {
+        let len = terms.iter().map(|(_, d)| d.partials.len()).max();
+        let mut partials = vec![0.0; len.unwrap_or(0)];
+        for (factor, dual) in terms {
+            for (partial, d) in partials.iter_mut().zip(&dual.partials) {
+                if *d != 0.0 {
+                    *partial += factor * d;
+                }
+            }
+        }
+        Self { value, partials }
+    }
+}
+
+impl Interpreter {
+    /// Evaluates the expression together with its partial derivatives with respect to the
+    /// given variables at the given values, using forward-mode automatic differentiation.
+    /// Unlike [`Expression::derivative`] nothing is built, so it stays cheap for large
+    /// formulas, but the result is only valid at this point.
+    ///
+    /// The variables shadow the ones defined on the interpreter. Functions defined with
+    /// [`define_fn`][`Interpreter::define_fn`] need a derivative rule, see
+    /// [`set_derivative`][`Interpreter::set_derivative`], the built-in ones have it. User-defined
+    /// functions are differentiated through their bodies and the built-in `if(cond, a, b)` like
+    /// `cond ? a : b`. Functions without a rule, other lazy ones included, and the factorial fail
+    /// with [`Error::NoDerivative`] if their arguments depend on the variables.
+    ///
+    /// ## Example usage
+    /// ```
+    /// use lexper::{Interpreter, Lexer, Parser};
+    ///
+    /// let mut lexer = Lexer::new("x^2 * y + sin(y)".to_string());
+    /// lexer.lex().unwrap();
+    /// let expr = Parser::new(lexer.tokens()).parse().unwrap();
+    ///
+    /// let interpreter = Interpreter::new();
+    /// let gradient = interpreter.gradient(&expr, &[("x", 3.0), ("y", 0.0)]).unwrap();
+    /// assert_eq!(gradient.value, 0.0);
+    /// // 2 * x * y and x^2 + cos(y)
+    /// assert_eq!(gradient.partials, [0.0, 10.0]);
+    /// ```
+    pub fn gradient(&self, expr: &Expression, variables: &[(&str, f64)]) -> Result<Dual> {
+        let locals: Vec<(&str, Dual)> = variables
+            .iter()
+            .enumerate()
+            .map(|(i, (name, value))| {
+                let mut partials = vec![0.0; variables.len()];
+                partials[i] = 1.0;
+                let dual = Dual {
+                    value: *value,
+                    partials,
+                };
+                (*name, dual)
+            })
+            .collect();
+
+        self.reset_steps();
+        let mut result = DualScope::new(self, &locals).eval(expr)?;
+        result.partials.resize(variables.len(), 0.0);
+        Ok(result)
+    }
+
+    /// Attaches a derivative rule to a function defined with
+    /// [`define_fn`][`Interpreter::define_fn`] on this interpreter, it's used by
+    /// [`gradient`][`Interpreter::gradient`]. The rule gets the same arguments as the function
+    /// and returns the partial derivative with respect to each of them. Redefining the function
+    /// removes the rule.
+    ///
+    /// Returns `false` and changes nothing if there's no such function, or it's not a native one
+    /// defined on this interpreter itself.
+    ///
+    /// ## Example usage
+    /// ```
+    /// use lexper::{Interpreter, Lexer, Parser};
+    ///
+    /// let mut interpreter = Interpreter::new();
+    /// interpreter.define_fn("hypot".to_string(), |args: &[f64]| Ok(args[0].hypot(args[1])));
+    /// interpreter.set_derivative("hypot", |args: &[f64]| {
+    ///     let length = args[0].hypot(args[1]);
+    ///     Ok(vec![args[0] / length, args[1] / length])
+    /// });
+    ///
+    /// let mut lexer = Lexer::new("hypot(x, 4)".to_string());
+    /// lexer.lex().unwrap();
+    /// let expr = Parser::new(lexer.tokens()).parse().unwrap();
+    /// let gradient = interpreter.gradient(&expr, &[("x", 3.0)]).unwrap();
+    /// assert_eq!(gradient.value, 5.0);
+    /// assert_eq!(gradient.partials, [0.6]);
+    /// ```
+    pub fn set_derivative<F: Fn(&[f64]) -> Result<Vec<f64>> + 'static>(
+        &mut self,
+        name: &str,
+        derivative: F,
+    ) -> bool {
+        if !matches!(self.fns.get(name), Some(Function::Native(_))) {
+            return false;
+        }
+        self.derivatives
+            .insert(name.to_string(), Box::new(derivative));
+        true
+    }
+
+    /// Returns the derivative rule of the function the name resolves to
+    fn get_derivative(&self, name: &str) -> Option<&DerivativeFn> {
+        if self.fns.contains_key(name) {
+            return self.derivatives.get(name);
+        }
+        if self.fn_resolver.as_ref().is_some_and(|r| r.has(name)) {
+            return None;
+        }
+        self.parent.as_ref()?.get_derivative(name)
+    }
+}
+
+/// Like [`Scope`][`super::Scope`], but the local bindings carry their partials
+struct DualScope<'a> {
+    interpreter: &'a Interpreter,
+    locals: &'a [(&'a str, Dual)],
+    parent: Option<&'a DualScope<'a>>,
+}
+
+impl<'a> DualScope<'a> {
+    fn new(interpreter: &'a Interpreter, locals: &'a [(&'a str, Dual)]) -> Self {
+        Self {
+            interpreter,
+            locals,
+            parent: None,
+        }
+    }
+
+    fn child(&'a self, locals: &'a [(&'a str, Dual)]) -> DualScope<'a> {
+        DualScope {
+            interpreter: self.interpreter,
+            locals,
+            parent: Some(self),
+        }
+    }
+
+    fn get_var(&self, name: &str) -> Option<Result<Dual>> {
+        let mut scope = Some(self);
+        while let Some(current) = scope {
+            if let Some((_, value)) = current.locals.iter().rev().find(|(l, _)| *l == name) {
+                return Some(Ok(value.clone()));
+            }
+            scope = current.parent;
+        }
+
+        let value = self.interpreter.get_var(name)?;
+        Some(value.map(Dual::constant))
+    }
+
+    fn eval(&self, expr: &Expression) -> Result<Dual> {
+        DualEvaluator { scope: self }.visit_expression(expr)
+    }
+
+    fn eval_call(&self, callee: &str, args: &[Expression], span: Span) -> Result<Dual> {
+        let ctx = self.interpreter;
+        let function = ctx.get_callee(callee, args.len(), span)?;
+        if let Callee::Function(Function::Lazy(function)) = function {
+            return self.eval_lazy(callee, function, args, span);
+        }
+
+        let args = args
+            .iter()
+            .map(|arg| self.eval(arg))
+            .collect::<Result<Vec<_>>>()?;
+        let values: Vec<f64> = args.iter().map(|arg| arg.value).collect();
+        let result = match function {
+            Callee::Function(Function::Native(function)) => function(&values)
+                .and_then(|value| self.chain_call(callee, value, &values, &args, span)),
+            Callee::Function(Function::User(function)) => {
+                let locals: Vec<(&str, Dual)> = function
+                    .params
+                    .iter()
+                    .map(String::as_str)
+                    .zip(args)
+                    .collect();
+                ctx.enter(span)?;
+                let result = DualScope::new(ctx, &locals).eval(&function.body);
+                ctx.leave();
+                result
+            }
+            Callee::Resolver(resolver) => resolver
+                .call(callee, &values)
+                .and_then(|value| self.chain_call(callee, value, &values, &args, span)),
+            Callee::Function(Function::Lazy(_)) => unreachable!("lazy calls are handled above"),
+        };
+        result.map_err(call_error(callee, span))
+    }
+
+    /// The built-in `if(cond, a, b)` changes like the branch taken, just like `cond ? a : b`.
+    /// Other lazy functions, one defined over `if` included, may evaluate their arguments any
+    /// way they like, so they're only called if the arguments use no local bindings, then the
+    /// result is a constant.
+    fn eval_lazy(
+        &self,
+        callee: &str,
+        function: &LazyFn,
+        args: &[Expression],
+        span: Span,
+    ) -> Result<Dual> {
+        if let ("if", [condition, then, otherwise]) = (callee, args)
+            && self.interpreter.is_builtin_if()
+        {
+            return if math::is_truthy(self.eval(condition)?.value) {
+                self.eval(then)
+            } else {
+                self.eval(otherwise)
+            };
+        }
+
+        if args.iter().any(|arg| self.uses_locals(arg)) {
+            return Err(Error::no_derivative(callee.to_owned(), span));
+        }
+        let scope = Scope::new(self.interpreter);
+        function(&LazyArgs::new(&scope, args))
+            .map(Dual::constant)
+            .map_err(call_error(callee, span))
+    }
+
+    /// Checks if the expression uses any of the local bindings
+    fn uses_locals(&self, expr: &Expression) -> bool {
+        let mut scope = Some(self);
+        while let Some(current) = scope {
+            if current.locals.iter().any(|(name, _)| expr.mentions(name)) {
+                return true;
+            }
+            scope = current.parent;
+        }
+        false
+    }
+
+    /// Applies the derivative rule of the called function to the partials of its arguments
+    fn chain_call(
+        &self,
+        callee: &str,
+        value: f64,
+        values: &[f64],
+        args: &[Dual],
+        span: Span,
+    ) -> Result<Dual> {
+        if args.iter().all(Dual::is_constant) {
+            return Ok(Dual::constant(value));
+        }
+        let Some(derivative) = self.interpreter.get_derivative(callee) else {
+            return Err(Error::no_derivative(callee.to_owned(), span));
+        };
+
+        let factors = derivative(values)?;
+        if factors.len() != args.len() {
+            return Err(Error::other(format!(
+                "Wrong number of partial derivatives, expected {}, but got {}",
+                args.len(),
+                factors.len()
+            )));
+        }
+        let terms: Vec<(f64, &Dual)> = factors.into_iter().zip(args).collect();
+        Ok(Dual::chain(value, &terms))
+    }
+}
+
+/// Walks the tree like the evaluator does, carrying the partials along with the values
+struct DualEvaluator<'s> {
+    scope: &'s DualScope<'s>,
+}
+
+impl<'e> Visitor<'e> for DualEvaluator<'_> {
+    type Output = Result<Dual>;
+
+    fn visit_expression(&mut self, expr: &'e Expression) -> Result<Dual> {
+        self.scope.interpreter.step(expr.span())?;
+        visit_node(self, expr)
+    }
+
+    fn visit_number(&mut self, value: f64, _span: Span) -> Result<Dual> {
+        Ok(Dual::constant(value))
+    }
+
+    fn visit_variable(&mut self, name: &'e str, span: Span) -> Result<Dual> {
+        self.scope
+            .get_var(name)
+            .unwrap_or_else(|| Err(Error::undefined(name.to_owned(), span)))
+    }
+
+    fn visit_unary(&mut self, op: UnaryOp, expr: &'e Expression, _span: Span) -> Result<Dual> {
+        let res = self.visit_expression(expr)?;
+        let value = Expression::apply_unary_op(&op, res.value);
+        match op {
+            UnaryOp::PLUS => Ok(res),
+            UnaryOp::MINUS => Ok(Dual::chain(value, &[(-1.0, &res)])),
+            UnaryOp::NOT => Ok(Dual::constant(value)),
+        }
+    }
+
+    fn visit_postfix(&mut self, op: PostfixOp, expr: &'e Expression, span: Span) -> Result<Dual> {
+        let res = self.visit_expression(expr)?;
+        let value = Expression::apply_postfix_op(&op, res.value, span)?;
+        if !res.is_constant() {
+            return Err(Error::no_derivative(op.to_string(), span));
+        }
+        Ok(Dual::constant(value))
+    }
+
+    fn visit_binary(
+        &mut self,
+        left: &'e Expression,
+        op: &'e OperatorType,
+        right: &'e Expression,
+        _span: Span,
+    ) -> Result<Dual> {
+        let left = self.visit_expression(left)?;
+
+        // logical operators don't evaluate the right side if the left one decides already
+        match op {
+            OperatorType::AND if !math::is_truthy(left.value) => {
+                return Ok(Dual::constant(0.0));
+            }
+            OperatorType::OR if math::is_truthy(left.value) => {
+                return Ok(Dual::constant(1.0));
+            }
+            _ => {}
+        }
+
+        let right = self.visit_expression(right)?;
+        Ok(apply_binary_op(op, &left, &right))
+    }
+
+    fn visit_conditional(
+        &mut self,
+        condition: &'e Expression,
+        then: &'e Expression,
+        otherwise: &'e Expression,
+        _span: Span,
+    ) -> Result<Dual> {
+        if math::is_truthy(self.visit_expression(condition)?.value) {
+            self.visit_expression(then)
+        } else {
+            self.visit_expression(otherwise)
+        }
+    }
+
+    fn visit_let(
+        &mut self,
+        name: &'e str,
+        value: &'e Expression,
+        body: &'e Expression,
+        _span: Span,
+    ) -> Result<Dual> {
+        let value = self.visit_expression(value)?;
+        self.scope.child(&[(name, value)]).eval(body)
+    }
+
+    fn visit_call(&mut self, callee: &'e str, args: &'e [Expression], span: Span) -> Result<Dual> {
+        self.scope.eval_call(callee, args, span)
+    }
+}
+
+fn apply_binary_op(op: &OperatorType, lhs: &Dual, rhs: &Dual) -> Dual {
+    let (l, r) = (lhs.value, rhs.value);
+    let value = Expression::apply_binary_op(op, l, r);
+    let (dl, dr) = match op {
+        OperatorType::PLUS => (1.0, 1.0),
+        OperatorType::MINUS => (1.0, -1.0),
+        OperatorType::MULTIPLY => (r, l),
+        OperatorType::DIVIDE => (1.0 / r, -value / r),
+        // `l % r` is `l - r * trunc(l / r)`
+        OperatorType::MODULO => (1.0, -(l / r).trunc()),
+        OperatorType::POWER => (r * l.powf(r - 1.0), value * l.ln()),
+        OperatorType::EQ
+        | OperatorType::NE
+        | OperatorType::LT
+        | OperatorType::LE
+        | OperatorType::GT
+        | OperatorType::GE
+        | OperatorType::AND
+        | OperatorType::OR => return Dual::constant(value),
+    };
+    Dual::chain(value, &[(dl, lhs), (dr, rhs)])
+}
+
+type BuiltinDerivative = fn(&[f64]) -> Result<Vec<f64>>;
+
+/// Derivative rules of the built-in functions, registered by [`Interpreter::new`]. They're
+/// applied only after the function itself accepted the arguments, so `max(x)` fails like it does
+/// when evaluated.
+pub(super) const BUILTIN_DERIVATIVES: [(&str, BuiltinDerivative); 10] = [
+    ("sin", |args| single_arg(args, |x| x.cos())),
+    ("cos", |args| single_arg(args, |x| -x.sin())),
+    ("tan", |args| single_arg(args, |x| 1.0 / x.cos().powi(2))),
+    ("cbrt", |args| {
+        single_arg(args, |x| 1.0 / (3.0 * x.cbrt().powi(2)))
+    }),
+    ("ln", |args| single_arg(args, |x| 1.0 / x)),
+    // constant between the jumps
+    ("ceil", |args| Ok(vec![0.0; args.len()])),
+    ("floor", |args| Ok(vec![0.0; args.len()])),
+    ("round", |args| Ok(vec![0.0; args.len()])),
+    ("max", |args| two_args(args, |a, b| a >= b)),
+    ("min", |args| two_args(args, |a, b| a <= b)),
+];
+
+/// The rule of a function of one argument, the ones after it are ignored just like the
+/// function ignores them
+fn single_arg(args: &[f64], derivative: fn(f64) -> f64) -> Result<Vec<f64>> {
+    let mut partials = vec![0.0; args.len()];
+    if let (Some(partial), Some(arg)) = (partials.first_mut(), args.first()) {
+        *partial = derivative(*arg);
+    }
+    Ok(partials)
+}
+
+/// The rule of `max` or `min`, the partial goes to the first argument if it's the one picked
+fn two_args(args: &[f64], picks_first: fn(f64, f64) -> bool) -> Result<Vec<f64>> {
+    match args {
+        [a, b] => {
+            let first = math::from_bool(picks_first(*a, *b));
+            Ok(vec![first, 1.0 - first])
+        }
+        _ => Err(Error::other(format!(
+            "Wrong number of arguments, expected 2, but got {}",
+            args.len()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(raw: &str) -> Result<Expression> {
+        let mut lexer = crate::Lexer::new(raw.to_string());
+        lexer.lex()?;
+        crate::Parser::new(lexer.tokens()).parse()
+    }
+
+    #[test]
+    fn test_gradient_matches_symbolic() {
+        let mut interpreter = Interpreter::new();
+        let sources = [
+            "x^3 - 2 * x / (y + 1)",
+            "sin(x) * cos(2 * y) + tan(x / 3)",
+            "cbrt(x) * cbrt(y) - cbrt(x / y)",
+            "x^y + 2^x + y^0.5 + ln(x * y)",
+            "x % 0.7 + max(x, y) * min(y^2, 2)",
+            "let a = x * y in let x = a + x in a * x",
+            "x > y ? x^2 : -y && 1",
+        ];
+        for raw in sources {
+            let expr = parse(raw).unwrap();
+            let dx = expr.derivative("x").unwrap();
+            let dy = expr.derivative("y").unwrap();
+            for (x, y) in [(0.3, 1.2), (1.1, 0.4), (2.6, 2.6)] {
+                let gradient = interpreter.gradient(&expr, &[("x", x), ("y", y)]).unwrap();
+                interpreter.define_var("x".to_string(), x);
+                interpreter.define_var("y".to_string(), y);
+                let expected = [
+                    interpreter.evaluate(expr.clone()).unwrap(),
+                    interpreter.evaluate(dx.clone()).unwrap(),
+                    interpreter.evaluate(dy.clone()).unwrap(),
+                ];
+                let actual = [gradient.value, gradient.partials[0], gradient.partials[1]];
+                for (expected, actual) in expected.into_iter().zip(actual) {
+                    assert!(
+                        (expected - actual).abs() < 1e-9 * expected.abs().max(1.0),
+                        "{raw} at ({x}, {y}): {expected} != {actual}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_gradient_functions() {
+        let mut lexer = crate::Lexer::new("sq(v) = v * v; 0".into());
+        lexer.lex().unwrap();
+        let program = crate::Parser::new(lexer.tokens()).parse_program().unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&program).unwrap();
+        interpreter.define_var("k".to_string(), 10.0);
+        interpreter.define_fn("twice".to_string(), |args: &[f64]| Ok(args[0] * 2.0));
+
+        let gradient = |interpreter: &Interpreter, raw: &str| {
+            interpreter.gradient(&parse(raw).unwrap(), &[("x", 3.0)])
+        };
+
+        // user-defined functions are differentiated through their bodies
+        let result = gradient(&interpreter, "sq(x) + k * x").unwrap();
+        assert_eq!(
+            result,
+            Dual {
+                value: 39.0,
+                partials: vec![16.0]
+            }
+        );
+        // constant arguments need no rule
+        let result = gradient(&interpreter, "twice(k) + 1").unwrap();
+        assert_eq!(
+            result,
+            Dual {
+                value: 21.0,
+                partials: vec![0.0]
+            }
+        );
+
+        let err = gradient(&interpreter, "1 + twice(x)").unwrap_err();
+        assert!(
+            matches!(err, Error::NoDerivative { ref callee, span } if callee == "twice" && span == Span::new(4, 12))
+        );
+        // only the branch taken is evaluated, like with a conditional
+        let result = gradient(&interpreter, "if(x > 1, sq(x), 1 / 0 + missing)").unwrap();
+        assert_eq!(
+            result,
+            Dual {
+                value: 9.0,
+                partials: vec![6.0]
+            }
+        );
+        // other lazy functions are evaluated as usual when they don't use the variables
+        interpreter.define_lazy_fn("first".to_string(), |args: &LazyArgs| args.eval(0));
+        let result = gradient(&interpreter, "first(k, 1) * x").unwrap();
+        assert_eq!(
+            result,
+            Dual {
+                value: 30.0,
+                partials: vec![10.0]
+            }
+        );
+        let err = gradient(&interpreter, "first(x)").unwrap_err();
+        assert!(matches!(err, Error::NoDerivative { ref callee, .. } if callee == "first"));
+        let err = gradient(&interpreter, "sq(x, 1)").unwrap_err();
+        assert!(matches!(err, Error::ArityMismatch { .. }));
+        // built-in functions fail or ignore extra arguments like when evaluated
+        let evaluate = |raw: &str| interpreter.evaluate(parse(raw).unwrap());
+        let err = gradient(&interpreter, "max(x)").unwrap_err();
+        assert_eq!(err.to_string(), evaluate("max(3)").unwrap_err().to_string());
+        let result = gradient(&interpreter, "sin(x, x)").unwrap();
+        assert_eq!(result.value, evaluate("sin(3, 3)").unwrap());
+        assert_eq!(result.partials, vec![3f64.cos()]);
+
+        // a lazy `if` defined over the built-in one is like any other lazy function
+        let mut custom = Interpreter::new();
+        custom.define_lazy_fn("if".to_string(), |args: &LazyArgs| args.eval(2));
+        let err = gradient(&custom, "if(x > 1, x, 0)").unwrap_err();
+        assert!(matches!(err, Error::NoDerivative { ref callee, .. } if callee == "if"));
+        let result = gradient(&custom, "if(1, 2, PI)").unwrap();
+        assert_eq!(result.value, std::f64::consts::PI);
+
+        assert!(!interpreter.set_derivative("if", |_: &[f64]| Ok(vec![0.0])));
+        assert!(interpreter.set_derivative("twice", |_: &[f64]| Ok(vec![2.0])));
+        let result = gradient(&interpreter, "twice(x^2)").unwrap();
+        assert_eq!(
+            result,
+            Dual {
+                value: 18.0,
+                partials: vec![12.0]
+            }
+        );
+
+        interpreter.set_derivative("twice", |_: &[f64]| Ok(vec![]));
+        let err = gradient(&interpreter, "twice(x)").unwrap_err();
+        assert!(matches!(err, Error::CallFailed { .. }));
+
+        // redefining a function drops its rule
+        interpreter.define_fn("twice".to_string(), |args: &[f64]| Ok(args[0] * 2.0));
+        assert!(gradient(&interpreter, "twice(x)").is_err());
+    }
+}
@@ -0,0 +1,906 @@
+mod compiled;
+mod dual;
+mod optimizer;
+mod vm;
+
+pub use compiled::CompiledExpr;
+pub use dual::{DerivativeFn, Dual};
+pub use vm::{Bytecode, Instruction};
+
+pub(crate) use optimizer::Optimizer;
+
+use crate::Error;
+use crate::Limits;
+use crate::Result;
+use crate::lexer::Span;
+use crate::math;
+use crate::resolver::{FunctionResolver, VariableResolver};
+use core::f64;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::{Expression, Program, Statement};
+
+/// A native function getting already evaluated arguments, see [`Interpreter::define_fn`]
+pub type LexpFn = Box<dyn Fn(&[f64]) -> Result<f64>>;
+/// A function getting its arguments unevaluated, see [`Interpreter::define_lazy_fn`]
+pub type LazyFn = Box<dyn Fn(&LazyArgs) -> Result<f64>>;
+
+pub(crate) enum Function {
+    Native(LexpFn),
+    Lazy(LazyFn),
+    User(UserFn),
+}
+
+/// What the name of a called function resolves to, see [`Interpreter::get_fn`]
+pub(crate) enum Callee<'a> {
+    Function(&'a Function),
+    Resolver(&'a dyn FunctionResolver),
+}
+
+/// A function written in expression syntax, like `f(x, y) = x^2 + y`
+pub(crate) struct UserFn {
+    pub(crate) params: Vec<String>,
+    pub(crate) body: Expression,
+}
+
+/// Variables visible to an expression while it's evaluated, local bindings like arguments of
+/// a user-defined function are looked up first, then the variables of the interpreter
+pub(crate) struct Scope<'a> {
+    interpreter: &'a Interpreter,
+    locals: Locals<'a>,
+    parent: Option<&'a Scope<'a>>,
+}
+
+/// Local bindings of a [`Scope`], borrowed from where they already are, so a scope is never
+/// allocated
+#[derive(Clone, Copy)]
+enum Locals<'a> {
+    /// A single binding, like `let x = 2 in x * x`
+    Binding(&'a str, f64),
+    /// Arguments of a user-defined function, bound to its parameters
+    Params(&'a [String], &'a [f64]),
+}
+
+impl<'a> Scope<'a> {
+    /// A scope with no local bindings
+    pub(crate) fn new(interpreter: &'a Interpreter) -> Self {
+        Self::with_params(interpreter, &[], &[])
+    }
+
+    /// A scope with the arguments bound to the parameters, the locals of the current scope are
+    /// not visible from it, so a function body sees only its own arguments
+    pub(crate) fn with_params(
+        interpreter: &'a Interpreter,
+        params: &'a [String],
+        args: &'a [f64],
+    ) -> Self {
+        Self {
+            interpreter,
+            locals: Locals::Params(params, args),
+            parent: None,
+        }
+    }
+
+    /// A nested scope, its binding shadows the ones of this scope, like `let x = 2 in x * x`
+    pub(crate) fn child(&'a self, name: &'a str, value: f64) -> Scope<'a> {
+        Scope {
+            interpreter: self.interpreter,
+            locals: Locals::Binding(name, value),
+            parent: Some(self),
+        }
+    }
+
+    pub(crate) fn interpreter(&self) -> &'a Interpreter {
+        self.interpreter
+    }
+
+    pub(crate) fn get_var(&self, name: &str) -> Option<Result<f64>> {
+        let mut scope = Some(self);
+        while let Some(current) = scope {
+            match current.locals {
+                Locals::Binding(local, value) if local == name => return Some(Ok(value)),
+                Locals::Params(params, args) => {
+                    if let Some(i) = params.iter().position(|param| param == name) {
+                        return Some(Ok(args[i]));
+                    }
+                }
+                Locals::Binding(..) => {}
+            }
+            scope = current.parent;
+        }
+
+        self.interpreter.get_var(name)
+    }
+}
+
+/// Wraps an error of a call. Errors pointing somewhere come from the arguments and are kept as
+/// they are, the rest is about the call itself.
+pub(crate) fn call_error(callee: &str, span: Span) -> impl FnOnce(Error) -> Error + '_ {
+    move |e| match e.span() {
+        Some(_) => e,
+        None => Error::call_failed(callee.to_owned(), e, span),
+    }
+}
+
+/// Evaluates the arguments of a call and passes their values to `call`. Calls with up to
+/// [`INLINE_ARGS`] arguments keep them on the stack, longer ones allocate.
+pub(crate) fn with_args<'t, T>(
+    args: &'t [T],
+    mut eval: impl FnMut(&'t T) -> Result<f64>,
+    call: impl FnOnce(&[f64]) -> Result<f64>,
+) -> Result<f64> {
+    if args.len() <= INLINE_ARGS {
+        let mut values = [0.0; INLINE_ARGS];
+        for (value, arg) in values.iter_mut().zip(args) {
+            *value = eval(arg)?;
+        }
+        call(&values[..args.len()])
+    } else {
+        let values = args.iter().map(eval).collect::<Result<Vec<_>>>()?;
+        call(&values)
+    }
+}
+
+/// Calls with up to this many arguments keep them on the stack while they're evaluated
+pub(crate) const INLINE_ARGS: usize = 8;
+
+/// Arguments of a lazy function call, each one is evaluated only when asked for
+pub struct LazyArgs<'a> {
+    args: &'a [Expression],
+    evaluator: ArgEvaluator<'a>,
+}
+
+/// How the arguments of a lazy call are evaluated, by walking the tree, with the compiled
+/// nodes of a [`CompiledExpr`] or by running their [`Bytecode`]
+enum ArgEvaluator<'a> {
+    Tree(&'a Scope<'a>),
+    Compiled(&'a [compiled::Node<'a>], &'a compiled::Frame<'a>),
+    Bytecode {
+        bytecode: &'a Bytecode<'a>,
+        chunks: &'a [usize],
+        vars: &'a [f64],
+        locals: &'a [f64],
+    },
+}
+
+impl<'a> LazyArgs<'a> {
+    pub(crate) fn new(scope: &'a Scope<'a>, args: &'a [Expression]) -> Self {
+        Self {
+            args,
+            evaluator: ArgEvaluator::Tree(scope),
+        }
+    }
+
+    fn compiled(
+        args: &'a [Expression],
+        nodes: &'a [compiled::Node<'a>],
+        frame: &'a compiled::Frame<'a>,
+    ) -> Self {
+        Self {
+            args,
+            evaluator: ArgEvaluator::Compiled(nodes, frame),
+        }
+    }
+
+    fn bytecode(
+        args: &'a [Expression],
+        bytecode: &'a Bytecode<'a>,
+        chunks: &'a [usize],
+        vars: &'a [f64],
+        locals: &'a [f64],
+    ) -> Self {
+        Self {
+            args,
+            evaluator: ArgEvaluator::Bytecode {
+                bytecode,
+                chunks,
+                vars,
+                locals,
+            },
+        }
+    }
+
+    /// Number of arguments passed to the call
+    pub fn len(&self) -> usize {
+        self.args.len()
+    }
+
+    /// Checks if the call has no arguments
+    pub fn is_empty(&self) -> bool {
+        self.args.is_empty()
+    }
+
+    /// Returns the argument expression without evaluating it
+    pub fn get(&self, index: usize) -> Option<&'a Expression> {
+        self.args.get(index)
+    }
+
+    /// Evaluates the argument at the given index
+    pub fn eval(&self, index: usize) -> Result<f64> {
+        let arg = self.args.get(index).ok_or(Error::other(format!(
+            "Not enough arguments, expected at least {}, but got {}",
+            index + 1,
+            self.args.len()
+        )))?;
+        match &self.evaluator {
+            ArgEvaluator::Tree(scope) => arg.eval(scope),
+            ArgEvaluator::Compiled(nodes, frame) => nodes[index].eval(frame),
+            ArgEvaluator::Bytecode {
+                bytecode,
+                chunks,
+                vars,
+                locals,
+            } => bytecode.run(chunks[index], vars, locals),
+        }
+    }
+}
+
+macro_rules! impl_single_arg {
+    ($fn_name:ident, $method:ident) => {
+        fn $fn_name(args: &[f64]) -> Result<f64> {
+            let arg = args.first().ok_or(Error::other(format!(
+                "Not enough arguments, expected 1, but got 0"
+            )))?;
+            Ok(arg.$method())
+        }
+    };
+}
+
+#[derive(Default)]
+pub struct Interpreter {
+    globals: HashMap<String, f64>,
+    fns: HashMap<String, Function>,
+    /// Functions of this interpreter which always return the same result for the same arguments
+    pure_fns: HashSet<String>,
+    /// Derivative rules of native functions of this interpreter, see [`Interpreter::gradient`]
+    derivatives: HashMap<String, DerivativeFn>,
+    /// Whether `if` of this interpreter is the built-in [`lazy_if`], differentiated like a
+    /// conditional, see [`Interpreter::gradient`]
+    builtin_if: bool,
+    var_resolver: Option<Box<dyn VariableResolver>>,
+    fn_resolver: Option<Box<dyn FunctionResolver>>,
+    parent: Option<Rc<Interpreter>>,
+    limits: Limits,
+    /// Nodes evaluated since the current evaluation started
+    steps: Cell<usize>,
+    /// How deep the evaluation is nested right now
+    depth: Cell<usize>,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        let mut obj = Self::default();
+        obj.define_var("PI".to_string(), f64::consts::PI);
+        obj.define_fn("sin".to_string(), sin);
+        obj.define_fn("cos".to_string(), cos);
+        obj.define_fn("tan".to_string(), tan);
+        obj.define_fn("max".to_string(), max);
+        obj.define_fn("min".to_string(), min);
+        obj.define_fn("ceil".to_string(), ceil);
+        obj.define_fn("floor".to_string(), floor);
+        obj.define_fn("round".to_string(), round);
+        obj.define_fn("cbrt".to_string(), cbrt);
+        obj.define_fn("ln".to_string(), ln);
+        obj.define_lazy_fn("if".to_string(), lazy_if);
+        obj.builtin_if = true;
+        for name in [
+            "sin", "cos", "tan", "max", "min", "ceil", "floor", "round", "cbrt", "ln",
+        ] {
+            obj.set_pure(name, true);
+        }
+        for (name, derivative) in dual::BUILTIN_DERIVATIVES {
+            obj.set_derivative(name, derivative);
+        }
+        obj
+    }
+
+    /// Creates an empty interpreter layered on top of another one. Variables and functions of
+    /// the parent are visible through it, but everything defined later goes into the child only,
+    /// so a single parent with constants and functions can be shared by many children.
+    ///
+    /// ## Example usage
+    /// ```
+    /// use std::rc::Rc;
+    /// use lexper::{Interpreter, Lexer, Parser};
+    ///
+    /// let mut base = Interpreter::new();
+    /// base.define_var("rate".to_string(), 0.07);
+    /// let base = Rc::new(base);
+    ///
+    /// let mut request = Interpreter::with_parent(Rc::clone(&base));
+    /// request.define_var("principal".to_string(), 1000.0);
+    ///
+    /// let mut lexer = Lexer::new("round(principal * (1 + rate))".to_string());
+    /// lexer.lex().unwrap();
+    /// let expr = Parser::new(lexer.tokens()).parse().unwrap();
+    /// assert_eq!(request.evaluate(expr).unwrap(), 1070.0);
+    /// ```
+    pub fn with_parent(parent: Rc<Interpreter>) -> Self {
+        Self {
+            parent: Some(parent),
+            ..Self::default()
+        }
+    }
+
+    pub fn define_var(&mut self, name: String, value: f64) {
+        self.globals.insert(name, value);
+    }
+
+    /// Defines a function getting already evaluated arguments. It's impure by default, see
+    /// [`set_pure`][`Interpreter::set_pure`], and has no derivative rule, see
+    /// [`set_derivative`][`Interpreter::set_derivative`].
+    pub fn define_fn<F: Fn(&[f64]) -> Result<f64> + 'static>(&mut self, name: String, function: F) {
+        self.forget_builtin(&name);
+        self.fns.insert(name, Function::Native(Box::new(function)));
+    }
+
+    /// Marks a function defined with [`define_fn`][`Interpreter::define_fn`] on this interpreter
+    /// as pure or impure. Calls of pure functions with constant arguments are folded by
+    /// [`optimize`][`Interpreter::optimize`], so a function which reads the clock or a random
+    /// generator must stay impure. Built-in functions are pure.
+    ///
+    /// Returns `false` and changes nothing if there's no such function, or it's not a native one
+    /// defined on this interpreter itself, like a lazy one or one of the parent.
+    pub fn set_pure(&mut self, name: &str, pure: bool) -> bool {
+        if !matches!(self.fns.get(name), Some(Function::Native(_))) {
+            return false;
+        }
+        if pure {
+            self.pure_fns.insert(name.to_string());
+        } else {
+            self.pure_fns.remove(name);
+        }
+        true
+    }
+
+    /// Defines a function which gets its arguments unevaluated and decides on its own which ones
+    /// to evaluate, the built-in `if(cond, then, else)` is one of those.
+    ///
+    /// ## Example usage
+    /// ```
+    /// use lexper::{Interpreter, LazyArgs, Parser, Lexer};
+    ///
+    /// let mut interpreter = Interpreter::new();
+    /// // returns the first argument which evaluates successfully
+    /// interpreter.define_lazy_fn("first_ok".to_string(), |args: &LazyArgs| {
+    ///     let mut last = lexper::Error::other("no arguments".to_string());
+    ///     for i in 0..args.len() {
+    ///         match args.eval(i) {
+    ///             Ok(value) => return Ok(value),
+    ///             Err(e) => last = e,
+    ///         }
+    ///     }
+    ///     Err(last)
+    /// });
+    ///
+    /// let mut lexer = Lexer::new("first_ok(missing, 2)".to_string());
+    /// lexer.lex().unwrap();
+    /// let expr = Parser::new(lexer.tokens()).parse().unwrap();
+    /// assert_eq!(interpreter.evaluate(expr).unwrap(), 2.0);
+    /// ```
+    pub fn define_lazy_fn<F: Fn(&LazyArgs) -> Result<f64> + 'static>(
+        &mut self,
+        name: String,
+        function: F,
+    ) {
+        self.forget_builtin(&name);
+        self.fns.insert(name, Function::Lazy(Box::new(function)));
+    }
+
+    /// Defines a function written in expression syntax, `params` are bound to the arguments
+    /// when it's called. That's what `f(x, y) = x^2 + y` in a [`Program`] does.
+    pub fn define_user_fn(&mut self, name: String, params: Vec<String>, body: Expression) {
+        self.forget_builtin(&name);
+        self.fns
+            .insert(name, Function::User(UserFn { params, body }));
+    }
+
+    /// Sets the resolver asked for variables which are not defined with
+    /// [`define_var`][`Interpreter::define_var`], see [`VariableResolver`]
+    pub fn set_variable_resolver<R: VariableResolver + 'static>(&mut self, resolver: R) {
+        self.var_resolver = Some(Box::new(resolver));
+    }
+
+    /// Sets the resolver asked for functions which are not defined on this interpreter, it's
+    /// consulted before the parent, just like the variable resolver, see [`FunctionResolver`]
+    pub fn set_function_resolver<R: FunctionResolver + 'static>(&mut self, resolver: R) {
+        self.fn_resolver = Some(Box::new(resolver));
+    }
+
+    /// Sets the limits checked while evaluating, [`max_depth`][`Limits::max_depth`] and
+    /// [`max_steps`][`Limits::max_steps`]
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Limits checked while evaluating, see [`set_limits`][`Interpreter::set_limits`]
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Names of all defined functions, including the ones of the parents
+    pub fn function_names(&self) -> impl Iterator<Item = &str> {
+        let parent_names = self
+            .parent
+            .iter()
+            .flat_map(|parent| parent.function_names())
+            .filter(|name| !self.fns.contains_key(*name));
+        // boxed, since the type of the iterator is recursive
+        let parent_names: Box<dyn Iterator<Item = &str>> = Box::new(parent_names);
+
+        self.fns.keys().map(String::as_str).chain(parent_names)
+    }
+
+    pub(crate) fn get_var(&self, name: &str) -> Option<Result<f64>> {
+        if let Some(value) = self.globals.resolve(name) {
+            return Some(value);
+        }
+        if let Some(value) = self.var_resolver.as_ref().and_then(|r| r.resolve(name)) {
+            return Some(value);
+        }
+        self.parent.as_ref()?.get_var(name)
+    }
+
+    /// Finds the called function in the same order as [`get_var`][`Interpreter::get_var`]
+    /// finds variables, the own functions, then the own resolver, then the parent
+    pub(crate) fn get_fn(&self, name: &str) -> Option<Callee<'_>> {
+        if let Some(function) = self.fns.get(name) {
+            return Some(Callee::Function(function));
+        }
+        if let Some(resolver) = self.fn_resolver.as_deref()
+            && resolver.has(name)
+        {
+            return Some(Callee::Resolver(resolver));
+        }
+        self.parent.as_ref()?.get_fn(name)
+    }
+
+    /// Finds the function of a call with `argc` arguments, like [`get_fn`][`Interpreter::get_fn`],
+    /// but fails if there's none or it's a user-defined one taking another number of them.
+    /// Every way of evaluating checks calls through here, so they fail the same way.
+    pub(crate) fn get_callee(&self, name: &str, argc: usize, span: Span) -> Result<Callee<'_>> {
+        match self.get_fn(name) {
+            Some(Callee::Function(Function::User(function))) if function.params.len() != argc => {
+                Err(Error::arity_mismatch(
+                    name.to_owned(),
+                    function.params.len(),
+                    argc,
+                    span,
+                ))
+            }
+            Some(callee) => Ok(callee),
+            None => Err(Error::undefined(name.to_owned(), span)),
+        }
+    }
+
+    /// Counts a node which starts being evaluated, fails if the evaluation takes too many steps
+    pub(crate) fn step(&self, span: Span) -> Result<()> {
+        let steps = self.steps.get() + 1;
+        self.steps.set(steps);
+        if steps > self.limits.max_steps {
+            return Err(Error::step_limit_exceeded(self.limits.max_steps, span));
+        }
+        Ok(())
+    }
+
+    /// Counts a call of a user-defined function, fails if calls nest too deep. The tree itself
+    /// is only as high as the parser allows, so it's the recursion which has to be limited.
+    /// Every successful call is paired with [`leave`][`Interpreter::leave`].
+    pub(crate) fn enter(&self, span: Span) -> Result<()> {
+        let depth = self.depth.get();
+        if depth >= self.limits.max_depth {
+            return Err(Error::nesting_too_deep(self.limits.max_depth, span));
+        }
+        self.depth.set(depth + 1);
+        Ok(())
+    }
+
+    pub(crate) fn leave(&self) {
+        self.depth.set(self.depth.get() - 1);
+    }
+
+    /// Starts counting steps of a new evaluation
+    pub(crate) fn reset_steps(&self) {
+        self.steps.set(0);
+    }
+
+    /// Evaluates the body of a user-defined function with the arguments bound to its parameters,
+    /// `span` is the one of the call
+    pub(crate) fn call_user(&self, function: &UserFn, args: &[f64], span: Span) -> Result<f64> {
+        self.enter(span)?;
+        let result = function
+            .body
+            .eval(&Scope::with_params(self, &function.params, args));
+        self.leave();
+        result
+    }
+
+    /// Checks if the function the name resolves to is a pure native one
+    /// Drops what was known about the function defined under this name before
+    fn forget_builtin(&mut self, name: &str) {
+        self.pure_fns.remove(name);
+        self.derivatives.remove(name);
+        if name == "if" {
+            self.builtin_if = false;
+        }
+    }
+
+    /// Checks if `if` calls the built-in [`lazy_if`], not a function defined over it
+    pub(crate) fn is_builtin_if(&self) -> bool {
+        if self.fns.contains_key("if") {
+            return self.builtin_if;
+        }
+        if self.fn_resolver.as_ref().is_some_and(|r| r.has("if")) {
+            return false;
+        }
+        self.parent.as_ref().is_some_and(|p| p.is_builtin_if())
+    }
+
+    pub(crate) fn is_pure(&self, name: &str) -> bool {
+        if self.fns.contains_key(name) {
+            return self.pure_fns.contains(name);
+        }
+        if self.fn_resolver.as_ref().is_some_and(|r| r.has(name)) {
+            return false;
+        }
+        self.parent.as_ref().is_some_and(|p| p.is_pure(name))
+    }
+}
+
+impl Interpreter {
+    pub fn evaluate(&self, expr: Expression) -> Result<f64> {
+        self.reset_steps();
+        expr.eval(&Scope::new(self))
+    }
+
+    /// Runs every statement of the program in order, assignments and function definitions
+    /// update this interpreter, so they're visible to the following statements and to later runs.
+    /// Returns the value of the last statement which has one, the value of an assignment is the
+    /// assigned one and function definitions have no value.
+    pub fn run(&mut self, program: &Program) -> Result<f64> {
+        let mut last = None;
+        for statement in &program.statements {
+            self.reset_steps();
+            match statement {
+                Statement::Expression(expr) => {
+                    last = Some(expr.eval(&Scope::new(self))?);
+                }
+                Statement::Assign { name, value, .. } => {
+                    let value = value.eval(&Scope::new(self))?;
+                    self.define_var(name.clone(), value);
+                    last = Some(value);
+                }
+                Statement::Function {
+                    name, params, body, ..
+                } => {
+                    self.define_user_fn(name.clone(), params.clone(), body.clone());
+                }
+            }
+        }
+
+        last.ok_or(Error::other("Program has no value to return".to_string()))
+    }
+}
+
+// builtins
+impl_single_arg!(sin, sin);
+impl_single_arg!(cos, cos);
+impl_single_arg!(tan, tan);
+impl_single_arg!(ceil, ceil);
+impl_single_arg!(floor, floor);
+impl_single_arg!(cbrt, cbrt);
+impl_single_arg!(round, round);
+impl_single_arg!(ln, ln);
+
+fn lazy_if(args: &LazyArgs) -> Result<f64> {
+    if args.len() != 3 {
+        return Err(Error::other(format!(
+            "Wrong number of arguments, expected 3, but got {}",
+            args.len()
+        )));
+    }
+
+    if math::is_truthy(args.eval(0)?) {
+        args.eval(1)
+    } else {
+        args.eval(2)
+    }
+}
+
+fn max(args: &[f64]) -> Result<f64> {
+    if args.len() != 2 {
+        return Err(Error::other(format!(
+            "Not enough arguments, expected 2, but got {}",
+            args.len()
+        )));
+    }
+
+    let arg1 = args[0];
+    let arg2 = args[1];
+    Ok(arg1.max(arg2))
+}
+
+fn min(args: &[f64]) -> Result<f64> {
+    if args.len() != 2 {
+        return Err(Error::other(format!(
+            "Not enough arguments, expected 2, but got {}",
+            args.len()
+        )));
+    }
+
+    let arg1 = args[0];
+    let arg2 = args[1];
+
+    Ok(arg1.min(arg2))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn eval_with(interpreter: &Interpreter, raw: &str) -> Result<f64> {
+        let mut lexer = crate::Lexer::new(raw.to_string());
+        lexer.lex()?;
+        let expr = crate::Parser::new(lexer.tokens()).parse()?;
+        interpreter.evaluate(expr)
+    }
+
+    #[test]
+    fn test_run_program() {
+        let mut lexer =
+            crate::Lexer::new("rate = 0.07; principal = 1000\nprincipal * (1 + rate)^10".into());
+        lexer.lex().unwrap();
+        let program = crate::Parser::new(lexer.tokens()).parse_program().unwrap();
+
+        let mut interpreter = Interpreter::new();
+        let result = interpreter.run(&program).unwrap();
+        assert_eq!(result, 1000.0 * 1.07f64.powf(10.0));
+        assert!(matches!(interpreter.get_var("rate"), Some(Ok(0.07))));
+        assert_eq!(eval_with(&interpreter, "principal").unwrap(), 1000.0);
+
+        assert!(interpreter.run(&Program::default()).is_err());
+    }
+
+    #[test]
+    fn test_user_functions() {
+        let mut lexer = crate::Lexer::new(
+            "f(x, y) = x^2 + y\nfact(n) = n <= 1 ? 1 : n * fact(n - 1)\nx = 10\nf(3, 4) + x".into(),
+        );
+        lexer.lex().unwrap();
+        let program = crate::Parser::new(lexer.tokens()).parse_program().unwrap();
+
+        let mut interpreter = Interpreter::new();
+        assert_eq!(interpreter.run(&program).unwrap(), 23.0);
+        assert_eq!(eval_with(&interpreter, "fact(5)").unwrap(), 120.0);
+        // arguments are local to the call and don't leak out or shadow the caller's variables
+        assert_eq!(eval_with(&interpreter, "f(1, x) + x").unwrap(), 21.0);
+        // more arguments than fit on the stack
+        let mut lexer = crate::Lexer::new("g(a, b, c, d, e, f, g, h, i) = a - i; 0".into());
+        lexer.lex().unwrap();
+        let program = crate::Parser::new(lexer.tokens()).parse_program().unwrap();
+        interpreter.run(&program).unwrap();
+        assert_eq!(
+            eval_with(&interpreter, "g(9, 8, 7, 6, 5, 4, 3, 2, let x = 1 in x)").unwrap(),
+            8.0
+        );
+
+        let err = eval_with(&interpreter, "f(1)").unwrap_err();
+        assert!(matches!(
+            err,
+            Error::ArityMismatch {
+                expected: 2,
+                found: 1,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_layered_interpreters() {
+        let mut base = Interpreter::new();
+        base.define_var("a".to_string(), 1.0);
+        base.define_fn("double".to_string(), |args: &[f64]| Ok(args[0] * 2.0));
+        let base = Rc::new(base);
+
+        let mut first = Interpreter::with_parent(Rc::clone(&base));
+        let mut second = Interpreter::with_parent(Rc::clone(&base));
+        first.define_var("b".to_string(), 10.0);
+        second.define_var("a".to_string(), 5.0);
+
+        assert_eq!(eval_with(&first, "double(a + b)").unwrap(), 22.0);
+        assert_eq!(
+            eval_with(&second, "double(a) + PI").unwrap(),
+            10.0 + f64::consts::PI
+        );
+        // overlays don't see each other and don't change the base
+        assert!(eval_with(&second, "b").is_err());
+        assert_eq!(eval_with(&base, "a").unwrap(), 1.0);
+
+        let names: Vec<&str> = first.function_names().collect();
+        assert!(names.contains(&"double") && names.contains(&"sin"));
+    }
+
+    #[test]
+    fn test_let_scopes() {
+        let mut lexer = crate::Lexer::new("x = 100; f(y) = x + y".into());
+        lexer.lex().unwrap();
+        let program = crate::Parser::new(lexer.tokens()).parse_program().unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&program).unwrap();
+
+        assert_eq!(eval_with(&interpreter, "let x = 2 in x * x").unwrap(), 4.0);
+        assert_eq!(
+            eval_with(&interpreter, "let x = 2 in let y = x + 1 in x * y").unwrap(),
+            6.0
+        );
+        // the binding is visible only in the body, and function bodies don't see it
+        assert_eq!(
+            eval_with(&interpreter, "(let x = 2 in x) + x").unwrap(),
+            102.0
+        );
+        assert_eq!(eval_with(&interpreter, "let x = 2 in f(1)").unwrap(), 101.0);
+    }
+
+    #[test]
+    fn test_lazy_if() {
+        let interpreter = Interpreter::new();
+        assert_eq!(eval_with(&interpreter, "if(1 < 2, 10, 20)").unwrap(), 10.0);
+        assert_eq!(eval_with(&interpreter, "if(0, 10, 20)").unwrap(), 20.0);
+        // the branch not taken is never evaluated
+        assert_eq!(eval_with(&interpreter, "if(1, 1, missing)").unwrap(), 1.0);
+        assert!(eval_with(&interpreter, "if(1, 2)").is_err());
+
+        let err = eval_with(&interpreter, "if(0, 1, missing)").unwrap_err();
+        assert!(matches!(err, Error::UndefinedIdentifier { ref name, .. } if name == "missing"));
+    }
+
+    #[test]
+    fn test_resolvers() {
+        use std::cell::RefCell;
+
+        struct Recording(RefCell<Vec<String>>);
+
+        impl VariableResolver for Rc<Recording> {
+            fn resolve(&self, name: &str) -> Option<Result<f64>> {
+                self.0.borrow_mut().push(name.to_string());
+                match name {
+                    "a" => Some(Ok(2.0)),
+                    "broken" => Some(Err(Error::other("connection lost".to_string()))),
+                    _ => None,
+                }
+            }
+        }
+
+        let recording = Rc::new(Recording(RefCell::new(Vec::new())));
+        let mut interpreter = Interpreter::new();
+        interpreter.define_var("b".to_string(), 3.0);
+        interpreter.set_variable_resolver(Rc::clone(&recording));
+
+        let mut fns: HashMap<String, LexpFn> = HashMap::new();
+        fns.insert(
+            "twice".to_string(),
+            Box::new(|args: &[f64]| Ok(args[0] * 2.0)),
+        );
+        interpreter.set_function_resolver(fns);
+
+        assert_eq!(eval_with(&interpreter, "twice(a) + b").unwrap(), 7.0);
+        // only the identifiers actually evaluated are asked for, defined variables come first
+        assert_eq!(eval_with(&interpreter, "0 && missing").unwrap(), 0.0);
+        assert_eq!(*recording.0.borrow(), ["a"]);
+
+        assert!(eval_with(&interpreter, "broken").is_err());
+        assert!(eval_with(&interpreter, "missing").is_err());
+        let err = eval_with(&interpreter, "thrice(1)").unwrap_err();
+        assert!(matches!(err, Error::UndefinedIdentifier { ref name, .. } if name == "thrice"));
+
+        // resolvers of the parent are used by the children
+        let child = Interpreter::with_parent(Rc::new(interpreter));
+        assert_eq!(eval_with(&child, "twice(a)").unwrap(), 4.0);
+    }
+
+    #[test]
+    fn test_lookup_order() {
+        use std::cell::RefCell;
+
+        // variables and functions are found in the same order, the own definitions, the own
+        // resolver, then the parent
+        let mut parent = Interpreter::new();
+        parent.define_var("v".to_string(), 1.0);
+        parent.define_fn("f".to_string(), |_| Ok(1.0));
+        let parent = Rc::new(parent);
+
+        let mut child = Interpreter::with_parent(Rc::clone(&parent));
+        child.set_variable_resolver(HashMap::from([("v".to_string(), 2.0)]));
+        let mut fns: HashMap<String, LexpFn> = HashMap::new();
+        fns.insert("f".to_string(), Box::new(|_: &[f64]| Ok(2.0)));
+        child.set_function_resolver(fns);
+        assert_eq!(eval_with(&child, "v * 10 + f()").unwrap(), 22.0);
+
+        child.define_var("v".to_string(), 3.0);
+        child.define_fn("f".to_string(), |_| Ok(3.0));
+        assert_eq!(eval_with(&child, "v * 10 + f()").unwrap(), 33.0);
+
+        // the function is looked up before its arguments are evaluated
+        struct Recording(RefCell<Vec<String>>);
+
+        impl VariableResolver for Rc<Recording> {
+            fn resolve(&self, name: &str) -> Option<Result<f64>> {
+                self.0.borrow_mut().push(name.to_string());
+                None
+            }
+        }
+
+        let recording = Rc::new(Recording(RefCell::new(Vec::new())));
+        let mut interpreter = Interpreter::new();
+        interpreter.set_variable_resolver(Rc::clone(&recording));
+        interpreter.set_function_resolver(HashMap::<String, LexpFn>::new());
+        let err = eval_with(&interpreter, "missing(expensive)").unwrap_err();
+        assert!(matches!(err, Error::UndefinedIdentifier { ref name, .. } if name == "missing"));
+        assert!(recording.0.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut lexer = crate::Lexer::new("count(n) = n <= 0 ? 0 : 1 + count(n - 1); 0".into());
+        lexer.lex().unwrap();
+        let program = crate::Parser::new(lexer.tokens()).parse_program().unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&program).unwrap();
+
+        assert_eq!(eval_with(&interpreter, "count(50)").unwrap(), 50.0);
+        // runaway recursion is an error instead of a stack overflow
+        let err = eval_with(&interpreter, "count(1000000)").unwrap_err();
+        assert!(
+            matches!(err, Error::NestingTooDeep { limit, .. } if limit == Limits::DEFAULT_MAX_DEPTH)
+        );
+        // and the depth is back to zero after it
+        assert_eq!(eval_with(&interpreter, "count(50)").unwrap(), 50.0);
+        // only calls nest, a long chain isn't limited by the depth
+        let chain = format!("{}1", "1 + ".repeat(999));
+        assert_eq!(eval_with(&interpreter, &chain).unwrap(), 1000.0);
+
+        interpreter.set_limits(Limits {
+            max_steps: 10,
+            ..Limits::default()
+        });
+        assert_eq!(eval_with(&interpreter, "1 + 2 + 3 + 4 + 5").unwrap(), 15.0);
+        let err = eval_with(&interpreter, "1 + 2 + 3 + 4 + 5 + 6").unwrap_err();
+        assert!(matches!(err, Error::StepLimitExceeded { limit: 10, .. }));
+        // every evaluation gets its own steps, including the compiled ones
+        assert!(eval_with(&interpreter, "1 + 2 + 3 + 4 + 5").is_ok());
+        let expr = Expression::Number(1.0, Default::default());
+        let compiled = interpreter.compile(&expr, &[]).unwrap();
+        assert!(compiled.eval(&[]).is_ok());
+        assert!(eval_with(&interpreter, "count(5)").is_err());
+
+        // compiled expressions count their steps too
+        let mut lexer = crate::Lexer::new("x + x + x + x + x + x".into());
+        lexer.lex().unwrap();
+        let expr = crate::Parser::new(lexer.tokens()).parse().unwrap();
+        let err = interpreter
+            .compile(&expr, &["x"])
+            .unwrap()
+            .eval(&[1.0])
+            .unwrap_err();
+        assert!(matches!(err, Error::StepLimitExceeded { limit: 10, span } if span == expr.span()));
+        let bytecode = interpreter.compile_bytecode(&expr, &["x"]).unwrap();
+        assert!(matches!(
+            bytecode.eval(&[1.0]).unwrap_err(),
+            Error::StepLimitExceeded { limit: 10, .. }
+        ));
+    }
+
+    #[test]
+    fn test_lazy_fn_sees_unevaluated_args() {
+        let mut interpreter = Interpreter::new();
+        interpreter.define_lazy_fn("count_args".to_string(), |args: &LazyArgs| {
+            Ok(args.len() as f64)
+        });
+        assert_eq!(
+            eval_with(&interpreter, "count_args(missing, 1 / 0, x)").unwrap(),
+            3.0
+        );
+    }
+}
@@ -0,0 +1,384 @@
+use crate::{
+    Expression,
+    lexer::{OperatorType, Span},
+    math,
+    parser::UnaryOp,
+};
+
+use super::{Callee, Function, Interpreter};
+
+impl Interpreter {
+    /// Shrinks the expression without changing its value, so it's cheaper to evaluate it many
+    /// times. Constant subtrees are folded into numbers, calls of pure functions with constant
+    /// arguments included, see [`set_pure`][`Interpreter::set_pure`]. Then the identities
+    /// `x * 1`, `1 * x`, `x / 1`, `x - 0`, `x ^ 1`, `+x` and `--x` are replaced with `x`, and
+    /// conditionals with a constant condition with the branch taken.
+    ///
+    /// Constants are computed exactly like the evaluation does, so NaN, infinity and the sign of
+    /// zero stay the same. Identities which don't hold for them are left alone, like `x * 0`
+    /// or `x + 0`, which is `0` rather than `-0` for `x = -0`. Anything which
+    /// fails to evaluate, like `(-1)!` or `sin()`, is kept as is, so the error is reported when
+    /// the expression is evaluated. Variables are never folded, their values may change.
+    ///
+    /// ## Example usage
+    /// ```
+    /// use lexper::{Interpreter, Lexer, Parser};
+    ///
+    /// let parse = |raw: &str| {
+    ///     let mut lexer = Lexer::new(raw.to_string());
+    ///     lexer.lex().unwrap();
+    ///     Parser::new(lexer.tokens()).parse().unwrap()
+    /// };
+    ///
+    /// let interpreter = Interpreter::new();
+    /// let optimized = interpreter.optimize(parse("2 * 3 + x * 1 - 0"));
+    /// assert_eq!(optimized, parse("6 + x"));
+    /// ```
+    pub fn optimize(&self, expr: Expression) -> Expression {
+        Optimizer {
+            interpreter: Some(self),
+            algebraic: false,
+        }
+        .optimize(expr)
+    }
+}
+
+/// Rewrites expressions into smaller ones with the same value, see [`Interpreter::optimize`].
+/// The algebraic one uses the identities of real numbers too, like `x * 0 = 0` or `x / x = 1`,
+/// so the value may change where `x` is infinite or zero. Derivatives are built with it, see
+/// [`Expression::derivative`].
+pub(crate) struct Optimizer<'i> {
+    /// Calls of the pure functions of the interpreter are folded, there's none for derivatives
+    interpreter: Option<&'i Interpreter>,
+    algebraic: bool,
+}
+
+impl Optimizer<'static> {
+    pub(crate) const ALGEBRAIC: Self = Self {
+        interpreter: None,
+        algebraic: true,
+    };
+}
+
+impl Optimizer<'_> {
+    fn optimize(&self, expr: Expression) -> Expression {
+        match expr {
+            Expression::Number(..) | Expression::Variable(..) => expr,
+            Expression::Unary { op, expr, span } => self.unary(op, self.optimize(*expr), span),
+            Expression::Postfix { op, expr, span } => {
+                let expr = self.optimize(*expr);
+                if let Expression::Number(n, _) = expr
+                    && let Ok(value) = Expression::apply_postfix_op(&op, n, span)
+                {
+                    return Expression::Number(value, span);
+                }
+                Expression::Postfix {
+                    op,
+                    expr: Box::new(expr),
+                    span,
+                }
+            }
+            Expression::Binary {
+                left,
+                op,
+                right,
+                span,
+            } => self.optimize_binary(self.optimize(*left), op, *right, span),
+            Expression::Conditional {
+                condition,
+                then,
+                otherwise,
+                span,
+            } => match self.optimize(*condition) {
+                Expression::Number(n, _) => {
+                    let taken = if math::is_truthy(n) {
+                        *then
+                    } else {
+                        *otherwise
+                    };
+                    self.optimize(taken)
+                }
+                condition => Expression::Conditional {
+                    condition: Box::new(condition),
+                    then: Box::new(self.optimize(*then)),
+                    otherwise: Box::new(self.optimize(*otherwise)),
+                    span,
+                },
+            },
+            Expression::Let {
+                name,
+                value,
+                body,
+                span,
+            } => Expression::Let {
+                name,
+                value: Box::new(self.optimize(*value)),
+                body: Box::new(self.optimize(*body)),
+                span,
+            },
+            Expression::Call { callee, args, span } => {
+                let args: Vec<Expression> =
+                    args.into_iter().map(|arg| self.optimize(arg)).collect();
+                if let Some(value) = self.fold_call(&callee, &args) {
+                    return Expression::Number(value, span);
+                }
+                Expression::Call { callee, args, span }
+            }
+        }
+    }
+
+    /// The left side is optimized already, the right one is optimized only if it's evaluated
+    fn optimize_binary(
+        &self,
+        left: Expression,
+        op: OperatorType,
+        right: Expression,
+        span: Span,
+    ) -> Expression {
+        // logical operators don't evaluate the right side if the left one decides already
+        if let Expression::Number(l, _) = left {
+            match op {
+                OperatorType::AND if !math::is_truthy(l) => return Expression::Number(0.0, span),
+                OperatorType::OR if math::is_truthy(l) => return Expression::Number(1.0, span),
+                _ => {}
+            }
+        }
+
+        let right = self.optimize(right);
+        self.binary(left, op, right, span)
+    }
+
+    /// Puts a unary node together from an optimized operand
+    pub(crate) fn unary(&self, op: UnaryOp, expr: Expression, span: Span) -> Expression {
+        match (op, expr) {
+            (op, Expression::Number(n, _)) => {
+                Expression::Number(Expression::apply_unary_op(&op, n), span)
+            }
+            (UnaryOp::PLUS, expr) => expr,
+            (
+                UnaryOp::MINUS,
+                Expression::Unary {
+                    op: UnaryOp::MINUS,
+                    expr,
+                    ..
+                },
+            ) => *expr,
+            (op, expr) => Expression::Unary {
+                op,
+                expr: Box::new(expr),
+                span,
+            },
+        }
+    }
+
+    /// Puts a binary node together from optimized operands
+    pub(crate) fn binary(
+        &self,
+        left: Expression,
+        op: OperatorType,
+        right: Expression,
+        span: Span,
+    ) -> Expression {
+        match (left, right) {
+            (Expression::Number(l, _), Expression::Number(r, _)) => {
+                Expression::Number(Expression::apply_binary_op(&op, l, r), span)
+            }
+            (left, Expression::Number(r, _)) if is_right_identity(&op, r) => left,
+            (Expression::Number(l, _), right) if is_left_identity(&op, l) => right,
+            (left, right) if self.algebraic => algebraic_binary(left, op, right, span),
+            (left, right) => Expression::Binary {
+                left: Box::new(left),
+                op,
+                right: Box::new(right),
+                span,
+            },
+        }
+    }
+
+    /// Calls a pure function if every argument is a constant and the call succeeds
+    fn fold_call(&self, callee: &str, args: &[Expression]) -> Option<f64> {
+        let interpreter = self.interpreter?;
+        if !interpreter.is_pure(callee) {
+            return None;
+        }
+        let Some(Callee::Function(Function::Native(function))) = interpreter.get_fn(callee) else {
+            return None;
+        };
+
+        let values = args
+            .iter()
+            .map(|arg| match arg {
+                Expression::Number(n, _) => Some(*n),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        function(&values).ok()
+    }
+}
+
+/// The identities of real numbers which don't hold for every float, like `x * 0 = 0`, which is
+/// NaN for an infinite `x`. Numbers go first in products, `3 * x^2` reads better than `x^2 * 3`.
+fn algebraic_binary(
+    left: Expression,
+    op: OperatorType,
+    right: Expression,
+    span: Span,
+) -> Expression {
+    let number = |n: f64| Expression::Number(n, span);
+    let neg = |expr: Expression| Optimizer::ALGEBRAIC.unary(UnaryOp::MINUS, expr, span);
+    let as_number = |expr: &Expression| match expr {
+        Expression::Number(n, _) => Some(*n),
+        _ => None,
+    };
+
+    match (as_number(&left), &op, as_number(&right)) {
+        (Some(0.0), OperatorType::PLUS, _) => right,
+        (_, OperatorType::PLUS | OperatorType::MINUS, Some(0.0)) => left,
+        (Some(0.0), OperatorType::MINUS, _) => neg(right),
+        (_, OperatorType::MINUS, _) if left == right => number(0.0),
+        (Some(0.0), OperatorType::MULTIPLY, _) | (_, OperatorType::MULTIPLY, Some(0.0)) => {
+            number(0.0)
+        }
+        (Some(-1.0), OperatorType::MULTIPLY, _) => neg(right),
+        (_, OperatorType::MULTIPLY, Some(-1.0)) => neg(left),
+        (None, OperatorType::MULTIPLY, Some(_)) => algebraic_binary(right, op, left, span),
+        (Some(0.0), OperatorType::DIVIDE, _) => number(0.0),
+        (_, OperatorType::DIVIDE, _) if left == right => number(1.0),
+        (_, OperatorType::POWER, Some(0.0)) => number(1.0),
+        _ => match (left, right) {
+            // `3 * (2 * x)` is `6 * x`
+            (
+                left @ Expression::Number(..),
+                Expression::Binary {
+                    left: inner,
+                    op: OperatorType::MULTIPLY,
+                    right: rest,
+                    ..
+                },
+            ) if op == OperatorType::MULTIPLY && matches!(*inner, Expression::Number(..)) => {
+                let factor =
+                    Optimizer::ALGEBRAIC.binary(left, OperatorType::MULTIPLY, *inner, span);
+                Optimizer::ALGEBRAIC.binary(factor, op, *rest, span)
+            }
+            (left, right) => Expression::Binary {
+                left: Box::new(left),
+                op,
+                right: Box::new(right),
+                span,
+            },
+        },
+    }
+}
+
+/// Checks if `x op value` is `x` for every `x`
+fn is_right_identity(op: &OperatorType, value: f64) -> bool {
+    match op {
+        OperatorType::MULTIPLY | OperatorType::DIVIDE | OperatorType::POWER => value == 1.0,
+        // `-0 + 0` is `0`, so only a negative zero can be added, and only a positive one taken
+        OperatorType::PLUS => value == 0.0 && value.is_sign_negative(),
+        OperatorType::MINUS => value == 0.0 && value.is_sign_positive(),
+        _ => false,
+    }
+}
+
+/// Checks if `value op x` is `x` for every `x`
+fn is_left_identity(op: &OperatorType, value: f64) -> bool {
+    match op {
+        OperatorType::MULTIPLY => value == 1.0,
+        OperatorType::PLUS => value == 0.0 && value.is_sign_negative(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Result;
+
+    fn parse(raw: &str) -> Result<Expression> {
+        let mut lexer = crate::Lexer::new(raw.to_string());
+        lexer.lex()?;
+        crate::Parser::new(lexer.tokens()).parse()
+    }
+
+    #[test]
+    fn test_optimize() {
+        let interpreter = Interpreter::new();
+        let cases = [
+            ("2 * 3 + x * 1 - 0", "6 + x"),
+            ("--x + -(-(2))", "x + 2"),
+            ("x ^ (3 - 2) / 1 - 0", "x"),
+            ("1 * (-0 + x)", "x"),
+            ("x + -0", "x"),
+            ("max(2, 3) * sin(0) + x", "0 + x"),
+            ("1 < 2 ? x : y", "x"),
+            ("0 && y || x", "0 || x"),
+            ("let a = 2 + 2 in a * 1", "let a = 4 in a"),
+            // not identities, `x * 0` is NaN for an infinite x
+            ("x * 0", "x * 0"),
+            ("0 - x", "0 - x"),
+            // not identities for `x = -0`
+            ("x + 0", "x + 0"),
+            ("0 + x", "0 + x"),
+            ("-x", "-x"),
+            // failing ones are left for the evaluation
+            ("max(1) + sin(x)", "max(1) + sin(x)"),
+        ];
+        for (raw, expected) in cases {
+            let optimized = interpreter.optimize(parse(raw).unwrap());
+            assert_eq!(optimized, parse(expected).unwrap(), "{raw}");
+        }
+
+        let optimized = interpreter.optimize(parse("(-1)! + x").unwrap());
+        assert!(matches!(
+            optimized,
+            Expression::Binary { ref left, .. } if matches!(**left, Expression::Postfix { .. })
+        ));
+    }
+
+    #[test]
+    fn test_optimize_keeps_values() {
+        let mut interpreter = Interpreter::new();
+        let sources = [
+            "x * 1 + 0 / 0",
+            "1 / 0 - x ^ 1",
+            "(0 / 0 == 0 / 0) + x",
+            "-(-(x)) % 0",
+            "x > 1 ? 2 ^ 1024 : -(1 / 0)",
+            "0 / 0 && x",
+            "x + 0 - 0",
+            "-0 + (0 + x) - -0",
+        ];
+        for x in [2.0, f64::NAN, f64::INFINITY, f64::NEG_INFINITY, -3.5, -0.0] {
+            interpreter.define_var("x".to_string(), x);
+            for raw in sources {
+                let expected = interpreter.evaluate(parse(raw).unwrap()).unwrap();
+                let optimized = interpreter.optimize(parse(raw).unwrap());
+                let actual = interpreter.evaluate(optimized).unwrap();
+                assert!(
+                    expected.to_bits() == actual.to_bits() || expected.is_nan() && actual.is_nan(),
+                    "{raw} with x = {x}: {expected} != {actual}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_pure_functions() {
+        let mut interpreter = Interpreter::new();
+        interpreter.define_fn("next".to_string(), |args: &[f64]| Ok(args[0] + 1.0));
+        let expr = parse("next(1) + sin(0)").unwrap();
+
+        assert_eq!(
+            interpreter.optimize(expr.clone()),
+            parse("next(1) + 0").unwrap()
+        );
+        assert!(!interpreter.set_pure("missing", true));
+        assert!(interpreter.set_pure("next", true));
+        assert_eq!(interpreter.optimize(expr.clone()), parse("2").unwrap());
+
+        // redefining a function makes it impure again
+        interpreter.define_fn("sin".to_string(), |_: &[f64]| Ok(42.0));
+        assert_eq!(interpreter.optimize(expr), parse("2 + sin(0)").unwrap());
+    }
+}
@@ -0,0 +1,584 @@
+use std::cell::Cell;
+
+use crate::{
+    Error, Expression, Result,
+    lexer::{OperatorType, Span},
+    math,
+    parser::{PostfixOp, UnaryOp},
+    resolver::FunctionResolver,
+};
+
+use super::{
+    Callee, Function, Interpreter, LazyArgs, LazyFn, LexpFn, UserFn, call_error,
+    compiled::VarSource,
+};
+
+/// A single instruction of the stack machine running [`Bytecode`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    /// Pushes a number
+    Push(f64),
+    /// Pushes the value of a variable passed to [`Bytecode::eval`]
+    Load(usize),
+    /// Pushes the value of a `let` binding, `0` is the innermost one
+    LoadLocal(usize),
+    /// Pushes a variable asked for from the resolver of the interpreter
+    LoadResolved(usize),
+
+    /// Replaces the value on top of the stack with the result of the operator
+    Unary(UnaryOp),
+    Postfix(PostfixOp, Span),
+    /// Pops the right operand and replaces the left one with the result
+    Binary(OperatorType),
+    /// Pops the arguments of the call and pushes the result
+    Call(usize),
+
+    /// Continues at the given instruction
+    Jump(usize),
+    /// Pops a value and continues at the given instruction if it's false
+    JumpIfFalse(usize),
+
+    /// Pops a value and binds it with `let`
+    Bind,
+    /// Drops the innermost `let` binding
+    Unbind,
+}
+
+/// An expression lowered to a flat sequence of [`Instruction`]s for a small stack machine.
+/// Evaluation is a single loop over the instructions instead of a recursion over the tree, so
+/// it's friendlier to the cache and nesting doesn't grow the native stack.
+///
+/// Created with [`Interpreter::compile_bytecode`], variables and functions are resolved the
+/// same way as for [`CompiledExpr`][`crate::CompiledExpr`].
+pub struct Bytecode<'i> {
+    interpreter: &'i Interpreter,
+    variables: Vec<String>,
+    /// The expression itself and the arguments of lazy calls, each one is run on its own
+    chunks: Vec<Chunk>,
+    entry: usize,
+    calls: Vec<Call<'i>>,
+    resolved: Vec<(String, Span)>,
+    /// The stack and the `let` bindings, kept between runs so evaluating doesn't allocate
+    stack: Cell<Vec<f64>>,
+    locals: Cell<Vec<f64>>,
+    /// Instructions don't keep spans, the step limit points at the whole expression
+    span: Span,
+}
+
+struct Chunk {
+    code: Vec<Instruction>,
+    max_stack: usize,
+    max_locals: usize,
+}
+
+struct Call<'i> {
+    callee: String,
+    target: CallTarget<'i>,
+    argc: usize,
+    span: Span,
+}
+
+enum CallTarget<'i> {
+    Native(&'i LexpFn),
+    /// Arguments of lazy calls are not on the stack, they're chunks run when asked for
+    Lazy(&'i LazyFn, Vec<Expression>, Vec<usize>),
+    User(&'i UserFn),
+    Resolved(&'i dyn FunctionResolver),
+}
+
+impl<'i> Bytecode<'i> {
+    /// Evaluates the expression, `vars` are the values of the variables in the order they were
+    /// given to [`Interpreter::compile_bytecode`]
+    pub fn eval(&self, vars: &[f64]) -> Result<f64> {
+        if vars.len() != self.variables.len() {
+            return Err(Error::other(format!(
+                "Wrong number of variables, expected {}, but got {}",
+                self.variables.len(),
+                vars.len()
+            )));
+        }
+
+        self.interpreter.reset_steps();
+        self.run(self.entry, vars, &[])
+    }
+
+    /// Instructions of the expression, without the ones of lazy call arguments
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.chunks[self.entry].code
+    }
+
+    /// Names of the variables, in the order their values are expected by
+    /// [`eval`][`Bytecode::eval`]
+    pub fn variables(&self) -> impl Iterator<Item = &str> {
+        self.variables.iter().map(String::as_str)
+    }
+
+    pub(crate) fn run(&self, chunk: usize, vars: &[f64], outer_locals: &[f64]) -> Result<f64> {
+        let chunk = &self.chunks[chunk];
+
+        // arguments of lazy calls are run while the buffers are taken, they get fresh ones
+        let mut stack = self.stack.take();
+        let mut locals = self.locals.take();
+        stack.clear();
+        stack.reserve(chunk.max_stack);
+        locals.clear();
+        locals.reserve(outer_locals.len() + chunk.max_locals);
+        locals.extend_from_slice(outer_locals);
+
+        let result = self.execute(chunk, vars, &mut stack, &mut locals);
+        self.stack.set(stack);
+        self.locals.set(locals);
+        result
+    }
+
+    fn execute(
+        &self,
+        chunk: &Chunk,
+        vars: &[f64],
+        stack: &mut Vec<f64>,
+        locals: &mut Vec<f64>,
+    ) -> Result<f64> {
+        let mut pc = 0;
+        while let Some(instruction) = chunk.code.get(pc) {
+            self.interpreter.step(self.span)?;
+            pc += 1;
+            match instruction {
+                Instruction::Push(n) => stack.push(*n),
+                Instruction::Load(slot) => stack.push(vars[*slot]),
+                Instruction::LoadLocal(depth) => {
+                    let local = locals
+                        .len()
+                        .checked_sub(depth + 1)
+                        .map(|index| locals[index])
+                        .ok_or_else(|| {
+                            Error::other(format!("`let` binding {depth} is not bound"))
+                        })?;
+                    stack.push(local);
+                }
+                Instruction::LoadResolved(index) => {
+                    let (name, span) = &self.resolved[*index];
+                    let value = self
+                        .interpreter
+                        .get_var(name)
+                        .unwrap_or_else(|| Err(Error::undefined(name.to_owned(), *span)))?;
+                    stack.push(value);
+                }
+                Instruction::Unary(op) => {
+                    let top = top(stack)?;
+                    *top = Expression::apply_unary_op(op, *top);
+                }
+                Instruction::Postfix(op, span) => {
+                    let top = top(stack)?;
+                    *top = Expression::apply_postfix_op(op, *top, *span)?;
+                }
+                Instruction::Binary(op) => {
+                    let right = pop(stack)?;
+                    let left = top(stack)?;
+                    *left = Expression::apply_binary_op(op, *left, right);
+                }
+                Instruction::Call(index) => {
+                    let value = self.call(&self.calls[*index], stack, vars, locals)?;
+                    stack.push(value);
+                }
+                Instruction::Jump(target) => pc = *target,
+                Instruction::JumpIfFalse(target) => {
+                    if !math::is_truthy(pop(stack)?) {
+                        pc = *target;
+                    }
+                }
+                Instruction::Bind => locals.push(pop(stack)?),
+                Instruction::Unbind => {
+                    locals.pop();
+                }
+            }
+        }
+
+        pop(stack)
+    }
+
+    fn call(&self, call: &Call, stack: &mut Vec<f64>, vars: &[f64], locals: &[f64]) -> Result<f64> {
+        let args_start = stack
+            .len()
+            .checked_sub(call.argc)
+            .ok_or_else(stack_underflow)?;
+        let args = &stack[args_start..];
+
+        let result = match &call.target {
+            CallTarget::Native(function) => function(args),
+            CallTarget::Lazy(function, exprs, chunks) => {
+                function(&LazyArgs::bytecode(exprs, self, chunks, vars, locals))
+            }
+            CallTarget::User(function) => self.interpreter.call_user(function, args, call.span),
+            CallTarget::Resolved(resolver) => resolver.call(&call.callee, args),
+        };
+        stack.truncate(args_start);
+
+        result.map_err(call_error(&call.callee, call.span))
+    }
+}
+
+// the lowering keeps the stack balanced, so these errors mean a bug in it rather than in the
+// expression
+fn pop(stack: &mut Vec<f64>) -> Result<f64> {
+    stack.pop().ok_or_else(stack_underflow)
+}
+
+fn top(stack: &mut [f64]) -> Result<&mut f64> {
+    stack.last_mut().ok_or_else(stack_underflow)
+}
+
+fn stack_underflow() -> Error {
+    Error::other("Bytecode stack underflow".to_string())
+}
+
+impl Interpreter {
+    /// Lowers the expression to [`Bytecode`] for evaluating it many times. `vars` are the
+    /// names of the variables whose values are passed to every [`eval`][`Bytecode::eval`],
+    /// everything else works like with [`compile`][`Interpreter::compile`].
+    ///
+    /// ## Example usage
+    /// ```
+    /// use lexper::{Interpreter, Lexer, Parser};
+    ///
+    /// let mut lexer = Lexer::new("x > 0 ? x * 2 : -x".to_string());
+    /// lexer.lex().unwrap();
+    /// let expr = Parser::new(lexer.tokens()).parse().unwrap();
+    ///
+    /// let interpreter = Interpreter::new();
+    /// let bytecode = interpreter.compile_bytecode(&expr, &["x"]).unwrap();
+    /// assert_eq!(bytecode.eval(&[3.0]).unwrap(), 6.0);
+    /// assert_eq!(bytecode.eval(&[-3.0]).unwrap(), 3.0);
+    /// ```
+    pub fn compile_bytecode(&self, expr: &Expression, vars: &[&str]) -> Result<Bytecode<'_>> {
+        let mut lowering = Lowering {
+            interpreter: self,
+            vars,
+            lets: Vec::new(),
+            chunks: Vec::new(),
+            calls: Vec::new(),
+            resolved: Vec::new(),
+        };
+        let entry = lowering.lower(expr)?;
+
+        Ok(Bytecode {
+            interpreter: self,
+            variables: vars.iter().map(|var| var.to_string()).collect(),
+            chunks: lowering.chunks,
+            entry,
+            calls: lowering.calls,
+            resolved: lowering.resolved,
+            stack: Cell::default(),
+            locals: Cell::default(),
+            span: expr.span(),
+        })
+    }
+}
+
+/// What's left to do while lowering, kept on an explicit stack instead of recursing
+enum Task<'e> {
+    Lower(&'e Expression),
+    Emit(Instruction),
+    /// Marks the position jumps to this label go to
+    Label(usize),
+    PushLet(&'e str),
+    PopLet,
+}
+
+struct Lowering<'i, 'v> {
+    interpreter: &'i Interpreter,
+    vars: &'v [&'v str],
+    lets: Vec<String>,
+    chunks: Vec<Chunk>,
+    calls: Vec<Call<'i>>,
+    resolved: Vec<(String, Span)>,
+}
+
+impl<'i> Lowering<'i, '_> {
+    /// Lowers the expression into a new chunk and returns its index
+    fn lower(&mut self, expr: &Expression) -> Result<usize> {
+        let mut code = Vec::new();
+        // jumps point to labels until the whole chunk is lowered
+        let mut labels: Vec<usize> = Vec::new();
+        let mut new_label = || {
+            labels.push(0);
+            labels.len() - 1
+        };
+        let mut positions = Vec::new();
+        let mut tasks = vec![Task::Lower(expr)];
+
+        while let Some(task) = tasks.pop() {
+            let expr = match task {
+                Task::Lower(expr) => expr,
+                Task::Emit(instruction) => {
+                    code.push(instruction);
+                    continue;
+                }
+                Task::Label(label) => {
+                    positions.push((label, code.len()));
+                    continue;
+                }
+                Task::PushLet(name) => {
+                    self.lets.push(name.to_string());
+                    continue;
+                }
+                Task::PopLet => {
+                    self.lets.pop();
+                    continue;
+                }
+            };
+
+            let sequence = match expr {
+                Expression::Number(n, _) => vec![Task::Emit(Instruction::Push(*n))],
+                Expression::Variable(name, span) => {
+                    vec![Task::Emit(self.variable(name, *span)?)]
+                }
+                Expression::Unary { op, expr, .. } => {
+                    vec![Task::Lower(expr), Task::Emit(Instruction::Unary(*op))]
+                }
+                Expression::Postfix { op, expr, span } => vec![
+                    Task::Lower(expr),
+                    Task::Emit(Instruction::Postfix(*op, *span)),
+                ],
+                // logical operators don't evaluate the right side if the left one decides already
+                Expression::Binary {
+                    left,
+                    op: OperatorType::AND,
+                    right,
+                    ..
+                } => {
+                    let (short, end) = (new_label(), new_label());
+                    vec![
+                        Task::Lower(left),
+                        Task::Emit(Instruction::JumpIfFalse(short)),
+                        Task::Emit(Instruction::Push(1.0)),
+                        Task::Lower(right),
+                        Task::Emit(Instruction::Binary(OperatorType::AND)),
+                        Task::Emit(Instruction::Jump(end)),
+                        Task::Label(short),
+                        Task::Emit(Instruction::Push(0.0)),
+                        Task::Label(end),
+                    ]
+                }
+                Expression::Binary {
+                    left,
+                    op: OperatorType::OR,
+                    right,
+                    ..
+                } => {
+                    let (rest, end) = (new_label(), new_label());
+                    vec![
+                        Task::Lower(left),
+                        Task::Emit(Instruction::JumpIfFalse(rest)),
+                        Task::Emit(Instruction::Push(1.0)),
+                        Task::Emit(Instruction::Jump(end)),
+                        Task::Label(rest),
+                        Task::Emit(Instruction::Push(0.0)),
+                        Task::Lower(right),
+                        Task::Emit(Instruction::Binary(OperatorType::OR)),
+                        Task::Label(end),
+                    ]
+                }
+                Expression::Binary {
+                    left, op, right, ..
+                } => vec![
+                    Task::Lower(left),
+                    Task::Lower(right),
+                    Task::Emit(Instruction::Binary(op.clone())),
+                ],
+                Expression::Conditional {
+                    condition,
+                    then,
+                    otherwise,
+                    ..
+                } => {
+                    let (other, end) = (new_label(), new_label());
+                    vec![
+                        Task::Lower(condition),
+                        Task::Emit(Instruction::JumpIfFalse(other)),
+                        Task::Lower(then),
+                        Task::Emit(Instruction::Jump(end)),
+                        Task::Label(other),
+                        Task::Lower(otherwise),
+                        Task::Label(end),
+                    ]
+                }
+                Expression::Let {
+                    name, value, body, ..
+                } => vec![
+                    Task::Lower(value),
+                    Task::Emit(Instruction::Bind),
+                    Task::PushLet(name),
+                    Task::Lower(body),
+                    Task::PopLet,
+                    Task::Emit(Instruction::Unbind),
+                ],
+                Expression::Call { callee, args, span } => {
+                    let (target, lowered_args) = self.call_target(callee, args, *span)?;
+                    self.calls.push(Call {
+                        callee: callee.clone(),
+                        target,
+                        argc: lowered_args.len(),
+                        span: *span,
+                    });
+
+                    let mut sequence: Vec<Task> = lowered_args.iter().map(Task::Lower).collect();
+                    sequence.push(Task::Emit(Instruction::Call(self.calls.len() - 1)));
+                    sequence
+                }
+            };
+
+            // tasks are popped from the end, so they're pushed in reverse
+            tasks.extend(sequence.into_iter().rev());
+        }
+
+        for (label, position) in positions {
+            labels[label] = position;
+        }
+        for instruction in &mut code {
+            if let Instruction::Jump(target) | Instruction::JumpIfFalse(target) = instruction {
+                *target = labels[*target];
+            }
+        }
+
+        let (max_stack, max_locals) = stack_usage(&code, &self.calls);
+        self.chunks.push(Chunk {
+            code,
+            max_stack,
+            max_locals,
+        });
+        Ok(self.chunks.len() - 1)
+    }
+
+    /// Finds the function of the call, returns the arguments which go on the stack
+    fn call_target<'e>(
+        &mut self,
+        callee: &str,
+        args: &'e [Expression],
+        span: Span,
+    ) -> Result<(CallTarget<'i>, &'e [Expression])> {
+        let target = match self.interpreter.get_callee(callee, args.len(), span)? {
+            Callee::Function(Function::Native(function)) => CallTarget::Native(function),
+            Callee::Function(Function::Lazy(function)) => {
+                let chunks = args
+                    .iter()
+                    .map(|arg| self.lower(arg))
+                    .collect::<Result<Vec<_>>>()?;
+                return Ok((CallTarget::Lazy(function, args.to_vec(), chunks), &[]));
+            }
+            Callee::Function(Function::User(function)) => CallTarget::User(function),
+            Callee::Resolver(resolver) => CallTarget::Resolved(resolver),
+        };
+
+        Ok((target, args))
+    }
+
+    fn variable(&mut self, name: &str, span: Span) -> Result<Instruction> {
+        if let Some(depth) = self.lets.iter().rev().position(|local| local == name) {
+            return Ok(Instruction::LoadLocal(depth));
+        }
+        if let Some(slot) = self.vars.iter().position(|var| *var == name) {
+            return Ok(Instruction::Load(slot));
+        }
+
+        match self.interpreter.var_source(name) {
+            Some(VarSource::Constant(value)) => Ok(Instruction::Push(value)),
+            Some(VarSource::Resolver) => {
+                self.resolved.push((name.to_string(), span));
+                Ok(Instruction::LoadResolved(self.resolved.len() - 1))
+            }
+            None => Err(Error::undefined(name.to_string(), span)),
+        }
+    }
+}
+
+/// Upper bounds of the stack and `let` bindings the code needs, both branches of a jump are
+/// counted as if they ran one after another
+fn stack_usage(code: &[Instruction], calls: &[Call]) -> (usize, usize) {
+    let (mut depth, mut max_stack, mut max_locals) = (0usize, 0, 0);
+    for instruction in code {
+        match instruction {
+            Instruction::Push(_)
+            | Instruction::Load(_)
+            | Instruction::LoadLocal(_)
+            | Instruction::LoadResolved(_) => depth += 1,
+            Instruction::Binary(_) | Instruction::JumpIfFalse(_) => depth = depth.saturating_sub(1),
+            Instruction::Bind => {
+                depth = depth.saturating_sub(1);
+                max_locals += 1;
+            }
+            Instruction::Call(index) => depth = depth.saturating_sub(calls[*index].argc) + 1,
+            Instruction::Unary(_)
+            | Instruction::Postfix(..)
+            | Instruction::Jump(_)
+            | Instruction::Unbind => {}
+        }
+        max_stack = max_stack.max(depth);
+    }
+    (max_stack, max_locals)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lower<'i>(interpreter: &'i Interpreter, raw: &str, vars: &[&str]) -> Result<Bytecode<'i>> {
+        let mut lexer = crate::Lexer::new(raw.to_string());
+        lexer.lex()?;
+        let expr = crate::Parser::new(lexer.tokens()).parse()?;
+        interpreter.compile_bytecode(&expr, vars)
+    }
+
+    #[test]
+    fn test_instructions() {
+        let interpreter = Interpreter::new();
+        let bytecode = lower(&interpreter, "x * 2 + max(x, 1)", &["x"]).unwrap();
+        assert_eq!(
+            bytecode.instructions(),
+            [
+                Instruction::Load(0),
+                Instruction::Push(2.0),
+                Instruction::Binary(OperatorType::MULTIPLY),
+                Instruction::Load(0),
+                Instruction::Push(1.0),
+                Instruction::Call(0),
+                Instruction::Binary(OperatorType::PLUS),
+            ]
+        );
+        assert_eq!(bytecode.eval(&[3.0]).unwrap(), 9.0);
+    }
+
+    #[test]
+    fn test_bytecode_matches_tree_walking() {
+        let mut lexer = crate::Lexer::new("sq(a) = a^2; k = 10".to_string());
+        lexer.lex().unwrap();
+        let program = crate::Parser::new(lexer.tokens()).parse_program().unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&program).unwrap();
+
+        let sources = [
+            "x^2 + y * PI - k",
+            "-x! + max(x, y) % 3",
+            "x > y ? sq(x) : sq(y)",
+            "let z = x * 2 in let x = z + 1 in x * z + y",
+            "let z = x in if(z, 1 / z, let w = y in w + z) && !y",
+            "x || y / 0",
+            "(x - 4)! + 1",
+        ];
+        for source in sources {
+            let bytecode = lower(&interpreter, source, &["x", "y"]).unwrap();
+            for (x, y) in [(3.0, 4.0), (0.0, -1.5), (5.0, 2.0)] {
+                let mut lexer =
+                    crate::Lexer::new(format!("let x = {x} in let y = {y} in {source}"));
+                lexer.lex().unwrap();
+                let expr = crate::Parser::new(lexer.tokens()).parse().unwrap();
+                let expected = interpreter.evaluate(expr);
+                let actual = bytecode.eval(&[x, y]);
+                match (expected, actual) {
+                    (Ok(e), Ok(a)) => assert!(e == a || e.is_nan() && a.is_nan(), "{source}"),
+                    (Err(_), Err(_)) => {}
+                    (e, a) => panic!("{source} with {x}, {y}: {e:?} != {a:?}"),
+                }
+            }
+        }
+    }
+}
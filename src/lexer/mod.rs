@@ -1,7 +1,9 @@
+mod number;
 mod token;
 
-use crate::{Error, Result};
-pub use token::{OperatorType, Token, TokenType};
+use crate::{Error, Limits, Result};
+pub(crate) use token::{Associativity, PREFIX_PRECEDENCE, TERNARY_PRECEDENCE};
+pub use token::{OperatorType, Span, Token, TokenType};
 
 /// This struct is responsible for "turning" source string into a list of tokens  
 /// Tokens makes sense only for the [Parser][`crate::Parser`] struct
@@ -18,47 +20,98 @@ pub use token::{OperatorType, Token, TokenType};
 pub struct Lexer {
     tokens: Vec<Token>,
     source: String,
+    options: LexerOptions,
+}
+
+/// Optional behaviour of the [`Lexer`], everything is disabled by default
+///
+/// ## Example usage
+/// ```
+/// use lexper::{Lexer, LexerOptions};
+///
+/// let options = LexerOptions {
+///     thousands_separator: Some(','),
+///     ..LexerOptions::default()
+/// };
+/// let mut lexer = Lexer::with_options("1,000,000.50 + max(1,000, 2)".to_string(), options);
+/// lexer.lex().unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LexerOptions {
+    /// Character accepted between groups of 3 digits in the integer part of a number, e.g. `,`
+    /// for `1,000,000`. [`Lexer::lex`] rejects separators which are a part of numbers or have a
+    /// meaning on their own, like `.`, digits, letters, `_`, operators and line breaks.
+    ///
+    /// When it's `,`, numbers inside of function call parentheses are not grouped, so
+    /// `max(1,000)` is still a call with two arguments.
+    pub thousands_separator: Option<char>,
+    /// The lexer checks [`max_input_len`][`Limits::max_input_len`] and
+    /// [`max_tokens`][`Limits::max_tokens`]
+    pub limits: Limits,
 }
 
 impl Lexer {
     /// Creates new lexer with provided source
     pub fn new(source: String) -> Self {
+        Self::with_options(source, LexerOptions::default())
+    }
+
+    /// Creates new lexer with provided source and options
+    pub fn with_options(source: String, options: LexerOptions) -> Self {
         Self {
             tokens: vec![],
             source,
+            options,
         }
     }
 
     /// Turns source string into a list of tokens populating the inner vector of tokens
     pub fn lex(&mut self) -> Result<()> {
-        let src = self.source.trim();
+        if let Some(separator) = self.options.thousands_separator {
+            check_separator(separator)?;
+        }
+
+        let limits = self.options.limits;
+        if self.source.len() > limits.max_input_len {
+            return Err(Error::input_too_long(
+                limits.max_input_len,
+                Span::new(limits.max_input_len, self.source.len()),
+            ));
+        }
 
-        let mut iter = src.chars().peekable();
+        let mut iter = self.source.chars().peekable();
 
         let mut line = 1usize;
         let mut col = 1usize;
+        // offset is counted in bytes, so it can be used to slice the source and build spans
         let mut offset = 0usize;
+        // one entry per open parenthesis, `true` if it belongs to a function call
+        let mut parens: Vec<bool> = Vec::new();
 
         while let Some(&c) = iter.peek() {
+            self.check_token_count()?;
             match c {
                 '0'..='9' => {
-                    let local_col = col;
                     let start = offset;
+                    // a comma inside of call parentheses always separates arguments
+                    let in_call = parens.last().copied().unwrap_or(false);
+                    let separator = self
+                        .options
+                        .thousands_separator
+                        .filter(|&sep| sep != ',' || !in_call);
+                    let (value, end) = number::scan_number(&self.source, start, separator)?;
 
-                    while let Some(&d) = iter.peek() {
-                        if d.is_ascii_digit() || d == '.' {
-                            offset += 1;
-                            col += 1;
-                            iter.next();
-                        } else {
-                            break;
-                        }
+                    self.tokens.push(Token::new(
+                        TokenType::LITERAL(value),
+                        Span::new(start, end),
+                        line,
+                        col,
+                    ));
+                    for _ in self.source[start..end].chars() {
+                        iter.next();
+                        col += 1;
                     }
-
-                    let slice = &self.source[start..offset];
-                    let is_num = slice.parse::<f64>()?;
-                    self.tokens
-                        .push(Token::new(TokenType::LITERAL(is_num), line, local_col));
+                    offset = end;
                 }
 
                 'a'..='z' | 'A'..='Z' | '_' => {
@@ -66,7 +119,7 @@ impl Lexer {
                     let start = offset;
                     while let Some(&d) = iter.peek() {
                         if d.is_alphanumeric() || d == '_' {
-                            offset += 1;
+                            offset += d.len_utf8();
                             col += 1;
                             iter.next();
                         } else {
@@ -76,6 +129,7 @@ impl Lexer {
                     let slice = &self.source[start..offset];
                     self.tokens.push(Token::new(
                         TokenType::IDENTIFIER(slice.to_string()),
+                        Span::new(start, offset),
                         line,
                         local_col,
                     ));
@@ -85,6 +139,7 @@ impl Lexer {
                     iter.next();
                     self.tokens.push(Token::new(
                         TokenType::OPERATOR(token::OperatorType::PLUS),
+                        Span::new(offset, offset + 1),
                         line,
                         col,
                     ));
@@ -96,6 +151,7 @@ impl Lexer {
                     iter.next();
                     self.tokens.push(Token::new(
                         TokenType::OPERATOR(token::OperatorType::MINUS),
+                        Span::new(offset, offset + 1),
                         line,
                         col,
                     ));
@@ -107,6 +163,7 @@ impl Lexer {
                     iter.next();
                     self.tokens.push(Token::new(
                         TokenType::OPERATOR(token::OperatorType::MULTIPLY),
+                        Span::new(offset, offset + 1),
                         line,
                         col,
                     ));
@@ -118,6 +175,7 @@ impl Lexer {
                     iter.next();
                     self.tokens.push(Token::new(
                         TokenType::OPERATOR(token::OperatorType::DIVIDE),
+                        Span::new(offset, offset + 1),
                         line,
                         col,
                     ));
@@ -129,6 +187,7 @@ impl Lexer {
                     iter.next();
                     self.tokens.push(Token::new(
                         TokenType::OPERATOR(token::OperatorType::MODULO),
+                        Span::new(offset, offset + 1),
                         line,
                         col,
                     ));
@@ -140,6 +199,7 @@ impl Lexer {
                     iter.next();
                     self.tokens.push(Token::new(
                         TokenType::OPERATOR(token::OperatorType::POWER),
+                        Span::new(offset, offset + 1),
                         line,
                         col,
                     ));
@@ -149,52 +209,184 @@ impl Lexer {
 
                 '(' => {
                     iter.next();
-                    self.tokens.push(Token::new(TokenType::LPAREN, line, col));
+                    let is_call = matches!(
+                        self.tokens.last().map(|t| t.ty()),
+                        Some(TokenType::IDENTIFIER(_))
+                    );
+                    parens.push(is_call);
+                    self.tokens.push(Token::new(
+                        TokenType::LPAREN,
+                        Span::new(offset, offset + 1),
+                        line,
+                        col,
+                    ));
                     col += 1;
                     offset += 1;
                 }
 
                 ')' => {
                     iter.next();
-                    self.tokens.push(Token::new(TokenType::RPAREN, line, col));
+                    parens.pop();
+                    self.tokens.push(Token::new(
+                        TokenType::RPAREN,
+                        Span::new(offset, offset + 1),
+                        line,
+                        col,
+                    ));
                     col += 1;
                     offset += 1;
                 }
 
                 ',' => {
                     iter.next();
-                    self.tokens.push(Token::new(TokenType::COMMA, line, col));
+                    self.tokens.push(Token::new(
+                        TokenType::COMMA,
+                        Span::new(offset, offset + 1),
+                        line,
+                        col,
+                    ));
+                    col += 1;
+                    offset += 1;
+                }
+
+                '?' => {
+                    iter.next();
+                    self.tokens.push(Token::new(
+                        TokenType::QUESTION,
+                        Span::new(offset, offset + 1),
+                        line,
+                        col,
+                    ));
                     col += 1;
                     offset += 1;
                 }
 
-                ' ' | '\t' => {
+                ':' => {
+                    iter.next();
+                    self.tokens.push(Token::new(
+                        TokenType::COLON,
+                        Span::new(offset, offset + 1),
+                        line,
+                        col,
+                    ));
                     col += 1;
                     offset += 1;
+                }
+
+                '<' | '>' | '=' | '!' | '&' | '|' => {
                     iter.next();
+                    let (r#type, len) = match (c, iter.peek()) {
+                        ('<', Some('=')) => (TokenType::OPERATOR(OperatorType::LE), 2),
+                        ('<', _) => (TokenType::OPERATOR(OperatorType::LT), 1),
+                        ('>', Some('=')) => (TokenType::OPERATOR(OperatorType::GE), 2),
+                        ('>', _) => (TokenType::OPERATOR(OperatorType::GT), 1),
+                        ('=', Some('=')) => (TokenType::OPERATOR(OperatorType::EQ), 2),
+                        ('=', _) => (TokenType::ASSIGN, 1),
+                        // the longest match wins, so `3!=6` is `3 != 6`, not `(3!) = 6`
+                        ('!', Some('=')) => (TokenType::OPERATOR(OperatorType::NE), 2),
+                        ('!', _) => (TokenType::BANG, 1),
+                        ('&', Some('&')) => (TokenType::OPERATOR(OperatorType::AND), 2),
+                        ('|', Some('|')) => (TokenType::OPERATOR(OperatorType::OR), 2),
+                        _ => {
+                            return Err(Error::unknown_char(
+                                line,
+                                col,
+                                c,
+                                Span::new(offset, offset + 1),
+                            ));
+                        }
+                    };
+                    if len == 2 {
+                        iter.next();
+                    }
+
+                    self.tokens.push(Token::new(
+                        r#type,
+                        Span::new(offset, offset + len),
+                        line,
+                        col,
+                    ));
+                    col += len;
+                    offset += len;
+                }
+
+                ' ' | '\t' | '\r' => {
+                    col += 1;
+                    offset += 1;
+                    iter.next();
+                }
+
+                ';' => {
+                    iter.next();
+                    self.tokens.push(Token::new(
+                        TokenType::SEMICOLON,
+                        Span::new(offset, offset + 1),
+                        line,
+                        col,
+                    ));
+                    col += 1;
+                    offset += 1;
                 }
 
                 '\n' => {
+                    iter.next();
+                    self.tokens.push(Token::new(
+                        TokenType::NEWLINE,
+                        Span::new(offset, offset + 1),
+                        line,
+                        col,
+                    ));
                     line += 1;
+                    col = 1;
                     offset += 1;
-                    iter.next();
                 }
 
                 c => {
-                    return Err(Error::unknown_char(line, col, c));
+                    return Err(Error::unknown_char(
+                        line,
+                        col,
+                        c,
+                        Span::new(offset, offset + c.len_utf8()),
+                    ));
                 }
             }
         }
 
-        Ok(())
+        self.check_token_count()
+    }
+
+    fn check_token_count(&self) -> Result<()> {
+        let limit = self.options.limits.max_tokens;
+        match self.tokens.get(limit) {
+            Some(token) => Err(Error::too_many_tokens(limit, token.span())),
+            None => Ok(()),
+        }
     }
 
     /// The only way to retrieve tokens, pass the output to the [Parser][`crate::Parser`] to process further
+    ///
+    /// Line breaks are tokens as well, [`TokenType::NEWLINE`], since they separate the statements
+    /// of a [`Program`][`crate::Program`]. They're there for a single expression too, the parser
+    /// skips them, but code going through the tokens on its own has to skip them as well.
     pub fn tokens(&self) -> &Vec<Token> {
         &self.tokens
     }
 }
 
+/// Checks that the thousands separator can't be confused with anything else in the source
+fn check_separator(separator: char) -> Result<()> {
+    let is_taken = separator.is_alphanumeric()
+        || separator.is_control()
+        || "._+-*/%^()?:<>=!&|;".contains(separator);
+    if is_taken {
+        return Err(Error::other(format!(
+            "'{}' can't be used as a thousands separator",
+            separator.escape_default()
+        )));
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -212,10 +404,211 @@ mod test {
         assert_eq!(
             tokens,
             &vec![
-                Token::new(TokenType::LITERAL(2.0), 1, 1),
-                Token::new(TokenType::OPERATOR(OperatorType::PLUS), 1, 3),
-                Token::new(TokenType::LITERAL(3.0), 1, 5),
+                Token::new(TokenType::LITERAL(2.0), Span::new(0, 1), 1, 1),
+                Token::new(
+                    TokenType::OPERATOR(OperatorType::PLUS),
+                    Span::new(2, 3),
+                    1,
+                    3
+                ),
+                Token::new(TokenType::LITERAL(3.0), Span::new(4, 5), 1, 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_token_spans() {
+        let str = "  sin(x1) +\n 42";
+        let mut lexer = Lexer::new(str.to_string());
+        lexer.lex().unwrap();
+
+        let slices: Vec<&str> = lexer
+            .tokens()
+            .iter()
+            .map(|t| &str[t.span().start..t.span().end])
+            .collect();
+        assert_eq!(slices, vec!["sin", "(", "x1", ")", "+", "\n", "42"]);
+        assert_eq!(lexer.tokens()[6].line(), 2);
+        assert_eq!(lexer.tokens()[6].col(), 2);
+    }
+
+    fn lex_literals(src: &str) -> Result<Vec<f64>> {
+        let mut lexer = Lexer::new(src.to_string());
+        lexer.lex()?;
+        Ok(lexer
+            .tokens()
+            .iter()
+            .filter_map(|t| match t.ty() {
+                TokenType::LITERAL(l) => Some(*l),
+                _ => None,
+            })
+            .collect())
+    }
+
+    #[test]
+    fn test_number_literals() {
+        assert_eq!(
+            lex_literals("1e-9 6.022E23 2.5e+3 1.5 7 0xFF 0b1010 0o17 0XfF").unwrap(),
+            vec![1e-9, 6.022e23, 2500.0, 1.5, 7.0, 255.0, 10.0, 15.0, 255.0]
+        );
+        assert_eq!(
+            lex_literals("1_000_000 1_000.000_1 0xFF_FF 1e1_0").unwrap(),
+            vec![1e6, 1000.0001, 65535.0, 1e10]
+        );
+
+        let malformed = [
+            ("1_", Span::new(1, 2)),
+            ("1__000", Span::new(1, 3)),
+            ("1_.5", Span::new(1, 2)),
+            ("0x_FF", Span::new(2, 3)),
+            ("1e_5", Span::new(2, 3)),
+            ("1e", Span::new(1, 2)),
+            ("2 * 1e+", Span::new(5, 7)),
+            ("0xZZ", Span::new(2, 3)),
+            ("0b102", Span::new(4, 5)),
+            ("0x", Span::new(0, 2)),
+            ("1.2.3", Span::new(3, 4)),
+        ];
+        for (src, expected) in malformed {
+            let err = lex_literals(src).unwrap_err();
+            assert!(
+                matches!(err, Error::MalformedLiteral { span, .. } if span == expected),
+                "{}: {:?}",
+                src,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_invalid_thousands_separator() {
+        for separator in ['.', '1', 'e', '_', '-', '(', '\n'] {
+            let options = LexerOptions {
+                thousands_separator: Some(separator),
+                ..LexerOptions::default()
+            };
+            let mut lexer = Lexer::with_options("1.5 + 2".to_string(), options);
+            assert!(lexer.lex().is_err(), "{separator:?}");
+        }
+
+        let options = LexerOptions {
+            thousands_separator: Some(' '),
+            ..LexerOptions::default()
+        };
+        let mut lexer = Lexer::with_options("1 000 + 2".to_string(), options);
+        lexer.lex().unwrap();
+        assert_eq!(lexer.tokens()[0].get_type(), TokenType::LITERAL(1000.0));
+    }
+
+    #[test]
+    fn test_thousands_separator() {
+        let lex = |src: &str| {
+            let options = LexerOptions {
+                thousands_separator: Some(','),
+                ..LexerOptions::default()
+            };
+            let mut lexer = Lexer::with_options(src.to_string(), options);
+            lexer.lex().unwrap();
+            lexer
+                .tokens()
+                .iter()
+                .map(|t| t.get_type())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(lex("1,000,000.50"), vec![TokenType::LITERAL(1_000_000.5)]);
+        // groups must have exactly 3 digits, otherwise the comma stays a comma
+        assert_eq!(
+            lex("1,00"),
+            vec![
+                TokenType::LITERAL(1.0),
+                TokenType::COMMA,
+                TokenType::LITERAL(0.0)
             ]
         );
+        // inside of a call the comma separates arguments, but grouping works in plain parens
+        assert_eq!(
+            lex("max(1,000) + (2,000)"),
+            vec![
+                TokenType::IDENTIFIER("max".to_string()),
+                TokenType::LPAREN,
+                TokenType::LITERAL(1.0),
+                TokenType::COMMA,
+                TokenType::LITERAL(0.0),
+                TokenType::RPAREN,
+                TokenType::OPERATOR(OperatorType::PLUS),
+                TokenType::LPAREN,
+                TokenType::LITERAL(2000.0),
+                TokenType::RPAREN,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_multi_char_operators() {
+        let mut lexer = Lexer::new("a<=b != !c&&d||e>f".to_string());
+        lexer.lex().unwrap();
+        let types: Vec<TokenType> = lexer.tokens().iter().map(|t| t.get_type()).collect();
+        let ident = |s: &str| TokenType::IDENTIFIER(s.to_string());
+
+        assert_eq!(
+            types,
+            vec![
+                ident("a"),
+                TokenType::OPERATOR(OperatorType::LE),
+                ident("b"),
+                TokenType::OPERATOR(OperatorType::NE),
+                TokenType::BANG,
+                ident("c"),
+                TokenType::OPERATOR(OperatorType::AND),
+                ident("d"),
+                TokenType::OPERATOR(OperatorType::OR),
+                ident("e"),
+                TokenType::OPERATOR(OperatorType::GT),
+                ident("f"),
+            ]
+        );
+        assert_eq!(lexer.tokens()[3].span(), Span::new(5, 7));
+
+        let err = Lexer::new("a & b".to_string()).lex().unwrap_err();
+        assert!(matches!(err, Error::UnknownChar { char: '&', .. }));
+
+        // `!=` is taken before a factorial, the factorial needs a space or parentheses
+        let mut lexer = Lexer::new("3!=6".to_string());
+        lexer.lex().unwrap();
+        let types: Vec<TokenType> = lexer.tokens().iter().map(|t| t.get_type()).collect();
+        assert_eq!(
+            types,
+            vec![
+                TokenType::LITERAL(3.0),
+                TokenType::OPERATOR(OperatorType::NE),
+                TokenType::LITERAL(6.0),
+            ]
+        );
+        assert_eq!(crate::eval("3!=6").unwrap(), 1.0);
+        assert_eq!(crate::eval("3! == 6").unwrap(), 1.0);
+        assert_eq!(crate::eval("3! != 6").unwrap(), 0.0);
+    }
+
+    #[test]
+    fn test_limits() {
+        let lex = |src: &str, limits: Limits| {
+            let options = LexerOptions {
+                limits,
+                ..LexerOptions::default()
+            };
+            Lexer::with_options(src.to_string(), options).lex()
+        };
+
+        let limits = Limits {
+            max_input_len: 5,
+            max_tokens: 3,
+            ..Limits::default()
+        };
+        assert!(lex("1 + 2", limits).is_ok());
+        let err = lex("10 + 2", limits).unwrap_err();
+        assert!(matches!(err, Error::InputTooLong { limit: 5, span } if span == Span::new(5, 6)));
+        let err = lex("1+2+3", limits).unwrap_err();
+        assert!(matches!(err, Error::TooManyTokens { limit: 3, span } if span == Span::new(3, 4)));
     }
 }
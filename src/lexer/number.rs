@@ -0,0 +1,179 @@
+use super::Span;
+use crate::{Error, Result};
+
+/// Scans a numeric literal starting at `start`, which must point to an ASCII digit.
+/// Supports decimals with an optional fraction and exponent (`6.022E23`, `1e-9`), integers
+/// with a radix prefix (`0xFF`, `0b1010`, `0o17`) and `_` between digits (`1_000_000`).
+///
+/// `separator` is a thousands separator allowed in the integer part of decimals, like `,` in
+/// `1,000,000.50`. It's only used when every group after the first one has exactly 3 digits.
+///
+/// Returns the value and the offset right after the literal.
+pub(super) fn scan_number(
+    source: &str,
+    start: usize,
+    separator: Option<char>,
+) -> Result<(f64, usize)> {
+    let bytes = source.as_bytes();
+
+    if bytes[start] == b'0' {
+        let radix = match bytes.get(start + 1) {
+            Some(b'x' | b'X') => Some(16),
+            Some(b'b' | b'B') => Some(2),
+            Some(b'o' | b'O') => Some(8),
+            _ => None,
+        };
+        if let Some(radix) = radix {
+            return scan_radix(source, start, radix);
+        }
+    }
+
+    let mut end = scan_digits(source, start)?;
+    if let Some(separator) = separator {
+        end = scan_groups(source, start, end, separator);
+    }
+
+    if bytes.get(end) == Some(&b'.') {
+        end = scan_digits(source, end + 1)?;
+        if bytes.get(end) == Some(&b'.') {
+            return Err(Error::malformed_literal(
+                "a number can't have more than one '.'".to_string(),
+                Span::new(end, end + 1),
+            ));
+        }
+    }
+
+    if let Some(b'e' | b'E') = bytes.get(end) {
+        let exp_start = end;
+        end += 1;
+        if let Some(b'+' | b'-') = bytes.get(end) {
+            end += 1;
+        }
+
+        let digits_end = scan_digits(source, end)?;
+        if digits_end == end {
+            return Err(Error::malformed_literal(
+                "expected digits after the exponent".to_string(),
+                Span::new(exp_start, end),
+            ));
+        }
+        end = digits_end;
+    }
+
+    let slice = &source[start..end];
+    let digits: String = slice
+        .chars()
+        .filter(|&c| c != '_' && Some(c) != separator)
+        .collect();
+    let value = digits
+        .parse::<f64>()
+        .map_err(|e| Error::parse_num_error(e, Span::new(start, end)))?;
+
+    Ok((value, end))
+}
+
+fn scan_radix(source: &str, start: usize, radix: u32) -> Result<(f64, usize)> {
+    let bytes = source.as_bytes();
+    let digits_start = start + 2;
+
+    // everything alphanumeric belongs to the literal, so `0xZZ` is reported as a bad digit
+    // instead of being split into `0x` and an identifier
+    let mut end = digits_start;
+    while end < bytes.len() && (bytes[end].is_ascii_alphanumeric() || bytes[end] == b'_') {
+        end += 1;
+    }
+
+    if end == digits_start {
+        return Err(Error::malformed_literal(
+            format!("expected digits after '{}'", &source[start..digits_start]),
+            Span::new(start, digits_start),
+        ));
+    }
+    check_underscores(bytes, digits_start, end)?;
+
+    let mut value = 0f64;
+    for (i, &b) in bytes[digits_start..end].iter().enumerate() {
+        if b == b'_' {
+            continue;
+        }
+        let digit = (b as char).to_digit(radix).ok_or_else(|| {
+            let offset = digits_start + i;
+            Error::malformed_literal(
+                format!("'{}' is not a valid base {} digit", b as char, radix),
+                Span::new(offset, offset + 1),
+            )
+        })?;
+        value = value * radix as f64 + digit as f64;
+    }
+
+    Ok((value, end))
+}
+
+/// Skips decimal digits and `_` separators between them, returns the offset after the last one
+fn scan_digits(source: &str, start: usize) -> Result<usize> {
+    let bytes = source.as_bytes();
+    let mut end = start;
+    while end < bytes.len() && (bytes[end].is_ascii_digit() || bytes[end] == b'_') {
+        end += 1;
+    }
+
+    check_underscores(bytes, start, end)?;
+    Ok(end)
+}
+
+/// Makes sure every run of `_` in the range is surrounded by digits on both sides
+fn check_underscores(bytes: &[u8], start: usize, end: usize) -> Result<()> {
+    let mut i = start;
+    while i < end {
+        if bytes[i] != b'_' {
+            i += 1;
+            continue;
+        }
+
+        let run_start = i;
+        while i < end && bytes[i] == b'_' {
+            i += 1;
+        }
+
+        let message = if run_start == start {
+            "a number can't start with '_'"
+        } else if i == end {
+            "a number can't end with '_'"
+        } else if i - run_start > 1 {
+            "only a single '_' is allowed between digits"
+        } else {
+            continue;
+        };
+        return Err(Error::malformed_literal(
+            message.to_string(),
+            Span::new(run_start, i),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Extends the integer part `start..end` with groups like `,000`, returns the new end.
+/// Nothing is consumed if the first group is longer than 3 digits or uses `_` already.
+fn scan_groups(source: &str, start: usize, end: usize, separator: char) -> usize {
+    let bytes = source.as_bytes();
+    if end - start > 3 || bytes[start..end].contains(&b'_') {
+        return end;
+    }
+
+    let mut end = end;
+    while let Some(rest) = source[end..].strip_prefix(separator) {
+        let group = rest.as_bytes();
+        let is_group = group.len() >= 3
+            && group[..3].iter().all(u8::is_ascii_digit)
+            && !group
+                .get(3)
+                .is_some_and(|b| b.is_ascii_digit() || *b == b'_');
+        if !is_group {
+            break;
+        }
+        end += separator.len_utf8() + 3;
+    }
+
+    end
+}
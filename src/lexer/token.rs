@@ -11,18 +11,77 @@ pub enum OperatorType {
 
     MODULO,
     POWER,
+
+    EQ,
+    NE,
+    LT,
+    LE,
+    GT,
+    GE,
+
+    AND,
+    OR,
 }
 
+/// Tells how a chain of operators with the same precedence is grouped, `a - b - c` is
+/// `(a - b) - c`, but `a ^ b ^ c` is `a ^ (b ^ c)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Associativity {
+    Left,
+    Right,
+}
+
+/// Precedence of prefix operators like `-2` or `!x`. They bind tighter than multiplication, but
+/// looser than `^`, so `-2^2` is `-(2^2)`, just like in math.
+pub(crate) const PREFIX_PRECEDENCE: u8 = 7;
+
+/// Precedence of the conditional expression `a ? b : c`, it's the loosest one, so `x > 0 ? x : -x`
+/// needs no parentheses.
+pub(crate) const TERNARY_PRECEDENCE: u8 = 0;
+
 impl OperatorType {
-    pub(crate) fn precendance(&self) -> u8 {
+    pub(crate) fn precedence(&self) -> u8 {
+        match self {
+            Self::OR => 1,
+            Self::AND => 2,
+            Self::EQ | Self::NE => 3,
+            Self::LT | Self::LE | Self::GT | Self::GE => 4,
+            Self::PLUS | Self::MINUS => 5,
+            Self::MULTIPLY | Self::DIVIDE | Self::MODULO => 6,
+            Self::POWER => 8,
+        }
+    }
+
+    pub(crate) fn associativity(&self) -> Associativity {
         match self {
-            Self::PLUS | Self::MINUS => 1,
-            Self::MULTIPLY | Self::DIVIDE => 2,
-            Self::POWER | Self::MODULO => 3,
+            Self::POWER => Associativity::Right,
+            _ => Associativity::Left,
         }
     }
 }
 
+impl std::fmt::Display for OperatorType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let symbol = match self {
+            Self::PLUS => "+",
+            Self::MINUS => "-",
+            Self::MULTIPLY => "*",
+            Self::DIVIDE => "/",
+            Self::MODULO => "%",
+            Self::POWER => "^",
+            Self::EQ => "==",
+            Self::NE => "!=",
+            Self::LT => "<",
+            Self::LE => "<=",
+            Self::GT => ">",
+            Self::GE => ">=",
+            Self::AND => "&&",
+            Self::OR => "||",
+        };
+        write!(f, "{symbol}")
+    }
+}
+
 /// The token type, literal, operator, parenthesis etc.
 #[derive(Debug, Clone, PartialEq)]
 #[allow(clippy::upper_case_acronyms)]
@@ -37,6 +96,65 @@ pub enum TokenType {
     IDENTIFIER(String), // e.g sin(), cos() etc.
 
     COMMA, // for future
+
+    BANG, // `!`, postfix factorial or prefix logical not
+
+    QUESTION, // `?` and `:` of the conditional expression
+    COLON,
+
+    ASSIGN,    // `=` in `x = 2`
+    SEMICOLON, // statement separators, `;` or a line break
+    NEWLINE,   // lexed in any source, only the parser knows if it's a program
+}
+
+/// A range of bytes in the original source string, `start` is inclusive and `end` is exclusive.
+/// Every [`Token`] and every [`Expression`][`crate::Expression`] carries one, so it can be used to
+/// point at the exact part of the source something came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// Creates new span from byte offsets
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// Returns the smallest span covering both `self` and `other`
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+
+    /// Length of the span in bytes
+    pub fn len(&self) -> usize {
+        self.end.saturating_sub(self.start)
+    }
+
+    /// Checks if the span covers no bytes at all
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+}
+
+impl std::fmt::Display for TokenType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LITERAL(l) => write!(f, "{l}"),
+            Self::OPERATOR(op) => write!(f, "{op}"),
+            Self::LPAREN => write!(f, "("),
+            Self::RPAREN => write!(f, ")"),
+            Self::IDENTIFIER(i) => write!(f, "{i}"),
+            Self::COMMA => write!(f, ","),
+            Self::BANG => write!(f, "!"),
+            Self::QUESTION => write!(f, "?"),
+            Self::COLON => write!(f, ":"),
+            Self::ASSIGN => write!(f, "="),
+            Self::SEMICOLON => write!(f, ";"),
+            Self::NEWLINE => write!(f, "line break"),
+        }
+    }
 }
 
 /// Represents some valuable information for our [Parser][`crate::Parser`], operators, literals
@@ -44,14 +162,20 @@ pub enum TokenType {
 #[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     pub(crate) r#type: TokenType,
+    pub(crate) span: Span,
     pub(crate) line: usize,
     pub(crate) col: usize,
 }
 
 impl Token {
     /// Creates new token instance
-    pub fn new(r#type: TokenType, line: usize, col: usize) -> Self {
-        Self { r#type, line, col }
+    pub fn new(r#type: TokenType, span: Span, line: usize, col: usize) -> Self {
+        Self {
+            r#type,
+            span,
+            line,
+            col,
+        }
     }
 
     /// Checks if this token is operator and returns the operator type
@@ -71,4 +195,19 @@ impl Token {
     pub fn ty(&self) -> &TokenType {
         &self.r#type
     }
+
+    /// Byte range of this token in the source string
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Line this token starts at, 1-based
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Column this token starts at, 1-based
+    pub fn col(&self) -> usize {
+        self.col
+    }
 }
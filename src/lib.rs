@@ -1,31 +1,135 @@
-//! Simple expression evaluation crate with custom lexer and parser. Currently doesn't have support
-//! for a factorial, but this feature is in TODO list.
+//! Simple expression evaluation crate with custom lexer and parser.
 //!
 //! ```
 //! # use lexper;
 //! let result = lexper::eval("sin(2) + 20^2").unwrap();
 //! assert_eq!(f64::round(result), 401.0);
+//!
+//! let result = lexper::eval("5! / 2").unwrap();
+//! assert_eq!(result, 60.0);
 //! ```
 
+mod diagnostic;
 mod interpreter;
 pub(crate) mod lexer;
+mod limits;
 mod r#macro;
+mod math;
 pub(crate) mod parser;
+mod resolver;
 
 pub mod error;
 pub(crate) use error::Result;
 
+pub use diagnostic::Diagnostic;
 pub use error::Error;
-pub use interpreter::Interpreter;
-pub use lexer::{Lexer, OperatorType, Token, TokenType};
-pub use parser::{Expression, Parser};
+pub use interpreter::{
+    Bytecode, CompiledExpr, DerivativeFn, Dual, Instruction, Interpreter, LazyArgs, LazyFn, LexpFn,
+};
+pub use lexer::{Lexer, LexerOptions, OperatorType, Span, Token, TokenType};
+pub use limits::Limits;
+pub use parser::{
+    Expression, Folder, FormatOptions, IdentifierRenderer, Markup, Parser, ParserOptions,
+    PostfixOp, Program, Statement, UnaryOp, VisitOutput, Visitor, fold_node, visit_all, visit_node,
+};
+pub use resolver::{FunctionResolver, VariableResolver};
 
 pub fn eval(expr: &str) -> Result<f64> {
     let mut lexer = Lexer::new(expr.to_string());
     lexer.lex()?;
 
     let mut parser = Parser::new(lexer.tokens());
-    let result = parser.parse_expression(0)?;
+    let result = parser.parse()?;
     let interpreter = Interpreter::new();
     interpreter.evaluate(result)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Evaluates the input and fails the test if evaluation panicked, errors are fine
+    fn assert_no_panic(input: &str) {
+        let result = std::panic::catch_unwind(|| eval(input));
+        assert!(result.is_ok(), "eval panicked on input {:?}", input);
+    }
+
+    #[test]
+    fn test_undefined_variable_is_error() {
+        let err = eval("2 * foo + 1").unwrap_err();
+        assert!(
+            matches!(err, Error::UndefinedIdentifier { ref name, span } if name == "foo" && span == Span::new(4, 7))
+        );
+    }
+
+    #[test]
+    fn test_eval_never_panics() {
+        let edge_cases = [
+            "",
+            " ",
+            "\n",
+            "(",
+            ")",
+            ",",
+            "()",
+            "((",
+            "))",
+            "1..2",
+            "1.2.3",
+            ".",
+            "-",
+            "+-+-",
+            "sin",
+            "sin(",
+            "sin()",
+            "sin(,)",
+            "max(1,)",
+            "max(1)",
+            "unknown(1)",
+            "PI(2)",
+            "  héllo",
+            "ü",
+            "é + 1",
+            "2 +\r\n3",
+            "\t(1)",
+            "1e",
+            "99999999999999999999999",
+            "0/0",
+            "1%0",
+            "(-1)^0.5",
+            "\u{0}",
+            "🙂",
+            "a\u{301}",
+        ];
+        for input in edge_cases {
+            assert_no_panic(input);
+        }
+
+        // every combination of up to 3 fragments
+        let fragments = [
+            "1", "2.5", "x", "PI", "sin", "max", "(", ")", ",", "!", "?", ":", "=", ";", "\n", "+",
+            "-", "*", "/", "%", "^", " ", ".", "é", "#",
+        ];
+        for a in fragments {
+            for b in fragments {
+                for c in fragments {
+                    assert_no_panic(&format!("{a}{b}{c}"));
+                }
+            }
+        }
+
+        // and a bunch of longer pseudo-random ones, the generator is seeded so failures reproduce
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for _ in 0..2000 {
+            let len = (state % 24) as usize;
+            let mut input = String::new();
+            for _ in 0..len {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                input.push_str(fragments[(state % fragments.len() as u64) as usize]);
+            }
+            assert_no_panic(&input);
+        }
+    }
+}
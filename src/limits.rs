@@ -0,0 +1,84 @@
+/// Bounds on the work lexing, parsing and evaluating may take, for input coming from untrusted
+/// users. Each one is checked by the stage it's about and reported with its own [`Error`]
+/// variant:
+///
+/// | limit | checked by | error |
+/// |-------|------------|-------|
+/// | [`max_input_len`][`Limits::max_input_len`] | [`Lexer`] | [`Error::InputTooLong`] |
+/// | [`max_tokens`][`Limits::max_tokens`] | [`Lexer`] | [`Error::TooManyTokens`] |
+/// | [`max_depth`][`Limits::max_depth`] | [`Parser`], [`Interpreter`] | [`Error::NestingTooDeep`] |
+/// | [`max_nodes`][`Limits::max_nodes`] | [`Parser`] | [`Error::TooManyNodes`] |
+/// | [`max_steps`][`Limits::max_steps`] | [`Interpreter`] | [`Error::StepLimitExceeded`] |
+///
+/// By default only the nesting depth is limited, that's what keeps deeply nested input like
+/// `((((...))))` from overflowing the stack. A long chain like `1 + 1 + ...` doesn't nest while
+/// parsing, but evaluating it recurses as deep as the chain is long, so limit
+/// [`max_nodes`][`Limits::max_nodes`] as well for untrusted input.
+///
+/// ## Example usage
+/// ```
+/// use lexper::{Error, Lexer, LexerOptions, Limits};
+///
+/// let limits = Limits {
+///     max_input_len: 16,
+///     ..Limits::default()
+/// };
+/// let options = LexerOptions {
+///     limits,
+///     ..LexerOptions::default()
+/// };
+/// let mut lexer = Lexer::with_options("1 + 2 + 3 + 4 + 5 + 6".to_string(), options);
+/// assert!(matches!(lexer.lex(), Err(Error::InputTooLong { .. })));
+/// ```
+///
+/// [`Error`]: crate::Error
+/// [`Error::InputTooLong`]: crate::Error::InputTooLong
+/// [`Error::TooManyTokens`]: crate::Error::TooManyTokens
+/// [`Error::NestingTooDeep`]: crate::Error::NestingTooDeep
+/// [`Error::TooManyNodes`]: crate::Error::TooManyNodes
+/// [`Error::StepLimitExceeded`]: crate::Error::StepLimitExceeded
+/// [`Lexer`]: crate::Lexer
+/// [`Parser`]: crate::Parser
+/// [`Interpreter`]: crate::Interpreter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Length of the source in bytes
+    pub max_input_len: usize,
+    /// Number of tokens the source is split into
+    pub max_tokens: usize,
+    /// How deep expressions may nest when parsing, like parentheses in `((1))`, prefix operators
+    /// in `--1`, the right side of `^` in `2^2^2` or arguments of calls, and how deep calls of
+    /// user-defined functions may nest while evaluating, so it limits recursion too
+    pub max_depth: usize,
+    /// Number of nodes of the parsed [`Expression`][`crate::Expression`]s
+    pub max_nodes: usize,
+    /// Number of nodes evaluated by a single [`evaluate`][`crate::Interpreter::evaluate`],
+    /// a single statement of [`run`][`crate::Interpreter::run`] or a single evaluation of a
+    /// compiled expression, [`Bytecode`][`crate::Bytecode`] counts its instructions instead
+    pub max_steps: usize,
+}
+
+impl Limits {
+    /// Default limit of the nesting depth, deep enough for any handwritten expression
+    pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+    /// No limits at all, deeply nested input may overflow the stack
+    pub fn unlimited() -> Self {
+        Self {
+            max_input_len: usize::MAX,
+            max_tokens: usize::MAX,
+            max_depth: usize::MAX,
+            max_nodes: usize::MAX,
+            max_steps: usize::MAX,
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_depth: Self::DEFAULT_MAX_DEPTH,
+            ..Self::unlimited()
+        }
+    }
+}
@@ -1,8 +1,10 @@
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+use lexper::Diagnostic;
+
+fn main() {
     let mut args = std::env::args();
     if args.len() != 2 {
         eprintln!("This program accepts only 1 argument, the expression itself");
-        return Ok(());
+        return;
     }
     args.next(); // consume path
 
@@ -10,7 +12,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .next()
         .expect("Expected an expression as a first argument");
 
-    let result = lexper::eval(&expr)?;
-    println!("{}", result);
-    Ok(())
+    match lexper::eval(&expr) {
+        Ok(result) => println!("{}", result),
+        Err(e) => {
+            eprint!("{}", Diagnostic::new(&e, &expr));
+            std::process::exit(1);
+        }
+    }
 }
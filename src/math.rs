@@ -0,0 +1,69 @@
+// Math functions not provided by std, used by the evaluator
+
+use std::f64::consts::PI;
+
+/// Lanczos approximation parameters, g = 7, n = 9
+const LANCZOS_G: f64 = 7.0;
+const LANCZOS_COEFFS: [f64; 9] = [
+    0.999_999_999_999_809_9,
+    676.520_368_121_885_1,
+    -1_259.139_216_722_402_8,
+    771.323_428_777_653_1,
+    -176.615_029_162_140_6,
+    12.507_343_278_686_905,
+    -0.138_571_095_265_720_12,
+    9.984_369_578_019_572e-6,
+    1.505_632_735_149_311_6e-7,
+];
+
+/// The gamma function, `gamma(n + 1) == n!` for non-negative integers.
+/// Returns NaN or infinity at the poles (zero and negative integers).
+pub(crate) fn gamma(x: f64) -> f64 {
+    if x < 0.5 {
+        // reflection formula, the approximation below is only good for x >= 0.5
+        return PI / ((PI * x).sin() * gamma(1.0 - x));
+    }
+
+    // gamma(171.62...) is the largest one fitting into f64
+    if x > 171.7 {
+        return f64::INFINITY;
+    }
+
+    let x = x - 1.0;
+    let t = x + LANCZOS_G + 0.5;
+    let mut sum = LANCZOS_COEFFS[0];
+    for (i, coeff) in LANCZOS_COEFFS.iter().enumerate().skip(1) {
+        sum += coeff / (x + i as f64);
+    }
+
+    // t^(x + 0.5) alone overflows long before the result does, so it's split in halves
+    let half_power = t.powf((x + 0.5) / 2.0);
+    (2.0 * PI).sqrt() * half_power * (-t).exp() * half_power * sum
+}
+
+/// Factorial generalised to real numbers via [`gamma`], `None` for negative integers where
+/// it's not defined. Integers are multiplied directly, so small results are exact.
+pub(crate) fn factorial(n: f64) -> Option<f64> {
+    if n.fract() == 0.0 {
+        if n < 0.0 {
+            return None;
+        }
+        // 171! doesn't fit into f64 anyway
+        if n > 170.0 {
+            return Some(f64::INFINITY);
+        }
+        return Some((2..=n as u64).fold(1.0, |acc, i| acc * i as f64));
+    }
+
+    Some(gamma(n + 1.0))
+}
+
+/// Logical value of a number, zero and NaN are false, everything else is true
+pub(crate) fn is_truthy(x: f64) -> bool {
+    x != 0.0 && !x.is_nan()
+}
+
+/// Numeric value of a boolean, `1` for true and `0` for false
+pub(crate) fn from_bool(b: bool) -> f64 {
+    if b { 1.0 } else { 0.0 }
+}
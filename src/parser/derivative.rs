@@ -0,0 +1,610 @@
+use std::ops::ControlFlow;
+
+use crate::{
+    Error, Result,
+    interpreter::Optimizer,
+    lexer::{OperatorType, Span},
+};
+
+use super::{Expression, Folder, PostfixOp, UnaryOp, Visitor};
+
+impl Expression {
+    /// Returns the derivative of the expression with respect to the variable, built with the
+    /// usual rules: sum, product, quotient, chain and the general power rule. Comparisons and
+    /// logical operators are constant almost everywhere, so their derivative is `0`.
+    ///
+    /// Functions are known by their names as they're defined by
+    /// [`Interpreter::new`][`crate::Interpreter::new`]: `sin`, `cos`, `tan`, `cbrt`, `ln`,
+    /// `ceil`, `floor`, `round`, `max`, `min` and `if`. Other functions, as well as the
+    /// factorial, fail with [`Error::NoDerivative`] unless their arguments don't depend on the
+    /// variable. A power with a variable exponent, like `2^x`, is differentiated with `ln`.
+    /// There's no interpreter to look the names up in, so a function redefined under one of
+    /// these names still gets the rule of the built-in one.
+    ///
+    /// The result is simplified while it's built, like [`Interpreter::optimize`] does it, but
+    /// with the identities of real numbers as well: `0 * x` and `x - x` are `0`, `x / x` is `1`
+    /// and `3 * (2 * x)` is `6 * x`, even where `x` is infinite or zero.
+    ///
+    /// [`Interpreter::optimize`]: crate::Interpreter::optimize
+    ///
+    /// ## Example usage
+    /// ```
+    /// use lexper::{Lexer, Parser};
+    ///
+    /// let parse = |raw: &str| {
+    ///     let mut lexer = Lexer::new(raw.to_string());
+    ///     lexer.lex().unwrap();
+    ///     Parser::new(lexer.tokens()).parse().unwrap()
+    /// };
+    ///
+    /// let derivative = parse("x^3 + 2 * x").derivative("x").unwrap();
+    /// assert_eq!(derivative, parse("3 * x^2 + 2"));
+    /// ```
+    pub fn derivative(&self, var: &str) -> Result<Expression> {
+        self.derive(var, None)
+    }
+
+    /// The derivative inside the bodies of `let`s, `bound` knows how their names change
+    fn derive(&self, var: &str, bound: Option<&Bound>) -> Result<Expression> {
+        let span = self.span();
+        let derivative = match self {
+            Self::Number(..) => number(0.0, span),
+            Self::Variable(name, _) => match Bound::find(bound, name) {
+                Some(derivative) => derivative.clone(),
+                None => number(if name == var { 1.0 } else { 0.0 }, span),
+            },
+            Self::Unary { op, expr, .. } => match op {
+                UnaryOp::PLUS => expr.derive(var, bound)?,
+                UnaryOp::MINUS => neg(expr.derive(var, bound)?, span),
+                UnaryOp::NOT => number(0.0, span),
+            },
+            Self::Postfix { op, expr, .. } => {
+                if is_zero(&expr.derive(var, bound)?) {
+                    return Ok(number(0.0, span));
+                }
+                match op {
+                    PostfixOp::FACTORIAL => return Err(Error::no_derivative(op.to_string(), span)),
+                }
+            }
+            Self::Binary {
+                left, op, right, ..
+            } => binary_derivative(self, left, op, right, var, bound)?,
+            Self::Conditional {
+                condition,
+                then,
+                otherwise,
+                ..
+            } => {
+                let then = then.derive(var, bound)?;
+                let otherwise = otherwise.derive(var, bound)?;
+                if then == otherwise {
+                    return Ok(then);
+                }
+                Self::Conditional {
+                    condition: condition.clone(),
+                    then: Box::new(then),
+                    otherwise: Box::new(otherwise),
+                    span,
+                }
+            }
+            Self::Let {
+                name, value, body, ..
+            } => let_derivative(self, name, value, body, var, bound)?,
+            Self::Call { callee, args, .. } => call_derivative(callee, args, var, bound, span)?,
+        };
+
+        Ok(derivative)
+    }
+
+    /// Checks if the variable is used anywhere in the expression
+    pub(crate) fn mentions(&self, var: &str) -> bool {
+        struct Mentions<'n>(&'n str);
+
+        impl<'a> Visitor<'a> for Mentions<'_> {
+            type Output = ControlFlow<()>;
+
+            fn visit_variable(&mut self, name: &'a str, _span: Span) -> ControlFlow<()> {
+                if name == self.0 {
+                    return ControlFlow::Break(());
+                }
+                ControlFlow::Continue(())
+            }
+
+            fn visit_let(
+                &mut self,
+                name: &'a str,
+                value: &'a Expression,
+                body: &'a Expression,
+                _span: Span,
+            ) -> ControlFlow<()> {
+                self.visit_expression(value)?;
+                // the body sees the binding instead
+                if name == self.0 {
+                    return ControlFlow::Continue(());
+                }
+                self.visit_expression(body)
+            }
+        }
+
+        Mentions(var).visit_expression(self).is_break()
+    }
+}
+
+/// A name bound by `let` together with its derivative, a number or a variable bound right
+/// outside of the `let`. The derivative of the body is taken once, with the names changing
+/// like this, instead of once for the variable and once for every name.
+struct Bound<'a> {
+    name: &'a str,
+    derivative: Expression,
+    parent: Option<&'a Bound<'a>>,
+}
+
+impl Bound<'_> {
+    fn find<'b>(mut bound: Option<&'b Bound<'b>>, name: &str) -> Option<&'b Expression> {
+        while let Some(current) = bound {
+            if current.name == name {
+                return Some(&current.derivative);
+            }
+            bound = current.parent;
+        }
+        None
+    }
+
+    /// Checks if a derivative is bound to this variable already, further out
+    fn is_taken(mut bound: Option<&Bound>, fresh: &str) -> bool {
+        while let Some(current) = bound {
+            if matches!(&current.derivative, Expression::Variable(name, _) if name == fresh) {
+                return true;
+            }
+            bound = current.parent;
+        }
+        false
+    }
+}
+
+fn binary_derivative(
+    expr: &Expression,
+    u: &Expression,
+    op: &OperatorType,
+    v: &Expression,
+    var: &str,
+    bound: Option<&Bound>,
+) -> Result<Expression> {
+    let span = expr.span();
+    let du = u.derive(var, bound)?;
+    let dv = v.derive(var, bound)?;
+    let (u, v) = (u.clone(), v.clone());
+
+    let derivative = match op {
+        OperatorType::PLUS => add(du, dv, span),
+        OperatorType::MINUS => sub(du, dv, span),
+        OperatorType::MULTIPLY => add(mul(du, v, span), mul(u, dv, span), span),
+        OperatorType::DIVIDE => div(
+            sub(mul(du, v.clone(), span), mul(u, dv, span), span),
+            pow(v, number(2.0, span), span),
+            span,
+        ),
+        // `u % v` is `u - v * trunc(u / v)`, and `trunc(u / v)` is `(u - u % v) / v`
+        OperatorType::MODULO => {
+            if is_zero(&dv) {
+                return Ok(du);
+            }
+            let quotient = div(sub(u, expr.clone(), span), v, span);
+            sub(du, mul(dv, quotient, span), span)
+        }
+        OperatorType::POWER => {
+            if is_zero(&dv) {
+                // v * u^(v - 1) * u'
+                let exponent = sub(v.clone(), number(1.0, span), span);
+                mul(mul(v, pow(u, exponent, span), span), du, span)
+            } else if is_zero(&du) {
+                // u^v * ln(u) * v'
+                mul(mul(expr.clone(), call("ln", vec![u], span), span), dv, span)
+            } else {
+                // u^v * (v' * ln(u) + v * u' / u)
+                let log = mul(dv, call("ln", vec![u.clone()], span), span);
+                let inner = add(log, div(mul(v, du, span), u, span), span);
+                mul(expr.clone(), inner, span)
+            }
+        }
+        OperatorType::EQ
+        | OperatorType::NE
+        | OperatorType::LT
+        | OperatorType::LE
+        | OperatorType::GT
+        | OperatorType::GE
+        | OperatorType::AND
+        | OperatorType::OR => number(0.0, span),
+    };
+
+    Ok(derivative)
+}
+
+/// `let a = f(x) in g(a, x)` changes with `x` directly and through `a`, so `a` changes like
+/// `f(x)` does while the body is differentiated. That derivative is bound to a fresh name
+/// right outside of the `let`, it's computed from the names visible there:
+/// `let da = f'(x) in let a = f(x) in g'(a, da, x)`.
+fn let_derivative(
+    expr: &Expression,
+    name: &str,
+    value: &Expression,
+    body: &Expression,
+    var: &str,
+    bound: Option<&Bound>,
+) -> Result<Expression> {
+    let span = expr.span();
+    let value_derivative = value.derive(var, bound)?;
+
+    // numbers and fresh names of outer derivatives can't be shadowed, so they're used as is
+    let fresh = match &value_derivative {
+        Expression::Number(..) => None,
+        Expression::Variable(derivative, _) if Bound::is_taken(bound, derivative) => None,
+        _ => {
+            let mut fresh = format!("d{name}");
+            let mut suffix = 1;
+            while fresh == var || occurs(expr, &fresh) || Bound::is_taken(bound, &fresh) {
+                suffix += 1;
+                fresh = format!("d{name}{suffix}");
+            }
+            Some(fresh)
+        }
+    };
+    let inner = Bound {
+        name,
+        derivative: match &fresh {
+            Some(fresh) => Expression::Variable(fresh.clone(), span),
+            None => value_derivative.clone(),
+        },
+        parent: bound,
+    };
+
+    let mut derivative = body.derive(var, Some(&inner))?;
+    if derivative.mentions(name) {
+        derivative = Expression::Let {
+            name: name.to_string(),
+            value: Box::new(value.clone()),
+            body: Box::new(derivative),
+            span,
+        };
+    }
+
+    if let Some(fresh) = fresh {
+        derivative = bind(fresh, value_derivative, derivative, span);
+    }
+    Ok(derivative)
+}
+
+/// Binds the value to the fresh name around the expression. A value used just once is put in
+/// its place instead, as long as no `let` on the way shadows a name it uses.
+fn bind(fresh: String, value: Expression, expr: Expression, span: Span) -> Expression {
+    let mut uses = Uses {
+        name: &fresh,
+        count: 0,
+        binders: Vec::new(),
+    };
+    uses.visit_expression(&expr);
+
+    if uses.count == 0 {
+        return expr;
+    }
+    if uses.count == 1 && !uses.binders.iter().any(|binder| value.mentions(binder)) {
+        return Substitute {
+            name: &fresh,
+            value: Some(value),
+        }
+        .fold_expression(expr);
+    }
+    Expression::Let {
+        name: fresh,
+        value: Box::new(value),
+        body: Box::new(expr),
+        span,
+    }
+}
+
+/// Counts the uses of a name and collects the names bound by `let`s
+struct Uses<'n, 'a> {
+    name: &'n str,
+    count: usize,
+    binders: Vec<&'a str>,
+}
+
+impl<'a> Visitor<'a> for Uses<'_, 'a> {
+    type Output = ();
+
+    fn visit_variable(&mut self, name: &'a str, _span: Span) {
+        if name == self.name {
+            self.count += 1;
+        }
+    }
+
+    fn visit_let(&mut self, name: &'a str, value: &'a Expression, body: &'a Expression, _: Span) {
+        self.binders.push(name);
+        self.visit_expression(value);
+        self.visit_expression(body);
+    }
+}
+
+/// Puts the value in place of the only use of the name
+struct Substitute<'n> {
+    name: &'n str,
+    value: Option<Expression>,
+}
+
+impl Folder for Substitute<'_> {
+    fn fold_variable(&mut self, name: String, span: Span) -> Expression {
+        match self.value.take() {
+            Some(value) if name == self.name => value,
+            value => {
+                self.value = value;
+                Expression::Variable(name, span)
+            }
+        }
+    }
+}
+
+/// Checks if the name is used anywhere in the expression, bound by a `let` included
+fn occurs(expr: &Expression, name: &str) -> bool {
+    struct Occurs<'n>(&'n str);
+
+    impl<'a> Visitor<'a> for Occurs<'_> {
+        type Output = ControlFlow<()>;
+
+        fn visit_variable(&mut self, name: &'a str, _span: Span) -> ControlFlow<()> {
+            if name == self.0 {
+                return ControlFlow::Break(());
+            }
+            ControlFlow::Continue(())
+        }
+
+        fn visit_let(
+            &mut self,
+            name: &'a str,
+            value: &'a Expression,
+            body: &'a Expression,
+            _span: Span,
+        ) -> ControlFlow<()> {
+            if name == self.0 {
+                return ControlFlow::Break(());
+            }
+            self.visit_expression(value)?;
+            self.visit_expression(body)
+        }
+    }
+
+    Occurs(name).visit_expression(expr).is_break()
+}
+
+fn call_derivative(
+    callee: &str,
+    args: &[Expression],
+    var: &str,
+    bound: Option<&Bound>,
+    span: Span,
+) -> Result<Expression> {
+    let derivatives = args
+        .iter()
+        .map(|arg| arg.derive(var, bound))
+        .collect::<Result<Vec<_>>>()?;
+    if derivatives.iter().all(is_zero) {
+        return Ok(number(0.0, span));
+    }
+
+    let derivative = match (callee, args, derivatives.as_slice()) {
+        ("sin", [u], [du]) => mul(call("cos", vec![u.clone()], span), du.clone(), span),
+        ("cos", [u], [du]) => mul(
+            neg(call("sin", vec![u.clone()], span), span),
+            du.clone(),
+            span,
+        ),
+        ("tan", [u], [du]) => div(
+            du.clone(),
+            pow(call("cos", vec![u.clone()], span), number(2.0, span), span),
+            span,
+        ),
+        ("cbrt", [u], [du]) => {
+            let cbrt = call("cbrt", vec![u.clone()], span);
+            let denominator = mul(number(3.0, span), pow(cbrt, number(2.0, span), span), span);
+            div(du.clone(), denominator, span)
+        }
+        ("ln", [u], [du]) => div(du.clone(), u.clone(), span),
+        // constant between the jumps
+        ("ceil" | "floor" | "round", [_], [_]) => number(0.0, span),
+        ("max" | "min", [a, b], [da, db]) => {
+            let op = if callee == "max" {
+                OperatorType::GE
+            } else {
+                OperatorType::LE
+            };
+            Expression::Conditional {
+                condition: Box::new(binary(a.clone(), op, b.clone(), span)),
+                then: Box::new(da.clone()),
+                otherwise: Box::new(db.clone()),
+                span,
+            }
+        }
+        ("if", [condition, _, _], [_, then, otherwise]) => call(
+            "if",
+            vec![condition.clone(), then.clone(), otherwise.clone()],
+            span,
+        ),
+        _ => return Err(Error::no_derivative(callee.to_string(), span)),
+    };
+
+    Ok(derivative)
+}
+
+// constructors simplifying as they go with the algebraic optimizer, so the derivative doesn't
+// drown in zeros and ones
+
+fn number(n: f64, span: Span) -> Expression {
+    Expression::Number(n, span)
+}
+
+fn is_zero(expr: &Expression) -> bool {
+    matches!(expr, Expression::Number(n, _) if *n == 0.0)
+}
+
+fn binary(left: Expression, op: OperatorType, right: Expression, span: Span) -> Expression {
+    Optimizer::ALGEBRAIC.binary(left, op, right, span)
+}
+
+fn call(callee: &str, args: Vec<Expression>, span: Span) -> Expression {
+    Expression::Call {
+        callee: callee.to_string(),
+        args,
+        span,
+    }
+}
+
+fn neg(expr: Expression, span: Span) -> Expression {
+    Optimizer::ALGEBRAIC.unary(UnaryOp::MINUS, expr, span)
+}
+
+fn add(left: Expression, right: Expression, span: Span) -> Expression {
+    binary(left, OperatorType::PLUS, right, span)
+}
+
+fn sub(left: Expression, right: Expression, span: Span) -> Expression {
+    binary(left, OperatorType::MINUS, right, span)
+}
+
+fn mul(left: Expression, right: Expression, span: Span) -> Expression {
+    binary(left, OperatorType::MULTIPLY, right, span)
+}
+
+fn div(left: Expression, right: Expression, span: Span) -> Expression {
+    binary(left, OperatorType::DIVIDE, right, span)
+}
+
+fn pow(base: Expression, exponent: Expression, span: Span) -> Expression {
+    binary(base, OperatorType::POWER, exponent, span)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(raw: &str) -> Result<Expression> {
+        let mut lexer = crate::Lexer::new(raw.to_string());
+        lexer.lex()?;
+        crate::Parser::new(lexer.tokens()).parse()
+    }
+
+    #[test]
+    fn test_derivative_rules() {
+        let interpreter = crate::Interpreter::new();
+        let cases = [
+            ("5", "0"),
+            ("x", "1"),
+            ("y", "0"),
+            ("-x", "-1"),
+            ("x + y", "1"),
+            ("x * y", "y"),
+            ("3 * x^2", "6 * x"),
+            ("x^3 + 2 * x", "3 * x^2 + 2"),
+            ("1 / x", "-1 / x^2"),
+            ("a^x", "a^x * ln(a)"),
+            ("x^x", "x^x * (ln(x) + 1)"),
+            ("sin(x^2)", "cos(x^2) * (2 * x)"),
+            ("cos(x)", "-sin(x)"),
+            ("x / x + x - x", "0"),
+            ("ln(x^2)", "2 * x / x^2"),
+            ("cbrt(y * x)", "y / (3 * cbrt(y * x)^2)"),
+            ("x > 0 ? x^2 : -x", "x > 0 ? 2 * x : -1"),
+            ("max(x, y)", "x >= y ? 1 : 0"),
+            ("floor(x) + (x < 1)", "0"),
+            ("let a = x^2 in a * y", "2 * x * y"),
+            (
+                "let a = x^2 in a * a",
+                "let da = 2 * x in let a = x^2 in da * a + a * da",
+            ),
+            ("let a = 3 * x in let x = a in x * y", "3 * y"),
+            // the outer `a` can't be moved into the `let`
+            (
+                "let b = a * x in let a = b in a * a",
+                "let db = a in let b = a * x in let a = b in db * a + a * db",
+            ),
+            ("f(y)", "0"),
+        ];
+        for (raw, expected) in cases {
+            let derivative = parse(raw).unwrap().derivative("x").unwrap();
+            // folds the parsed `-1` into a number, like the derivative has it
+            let expected = interpreter.optimize(parse(expected).unwrap());
+            assert_eq!(derivative, expected, "{raw}");
+        }
+
+        // `ln` is built in, so a variable exponent needs nothing defined
+        let mut interpreter = crate::Interpreter::new();
+        interpreter.define_var("x".to_string(), 3.0);
+        let derivative = parse("2^x").unwrap().derivative("x").unwrap();
+        let expected = 8.0 * 2f64.ln();
+        assert!((interpreter.evaluate(derivative).unwrap() - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_derivative_matches_numeric() {
+        let sources = [
+            "x^3 - 2 * x / (x + 1)",
+            "sin(x) * cos(2 * x) + tan(x / 3)",
+            "cbrt(x) * cbrt(x^2 + 1) - cbrt(1 / x)",
+            "x^x + 2^x + x^0.5",
+            "x % 0.7 + max(x, 1.5) * min(x^2, 2)",
+            "let a = x * 2 in let x = a + x in a * x",
+            "if(x > 1, x^2, -x)",
+        ];
+        let h = 1e-6;
+        for raw in sources {
+            let expr = parse(raw).unwrap();
+            let derivative = expr.derivative("x").unwrap();
+            for x in [0.3, 1.1, 2.6] {
+                let eval = |expr: &Expression, x: f64| {
+                    let mut interpreter = crate::Interpreter::new();
+                    interpreter.define_var("x".to_string(), x);
+                    interpreter.evaluate(expr.clone()).unwrap()
+                };
+                let numeric = (eval(&expr, x + h) - eval(&expr, x - h)) / (2.0 * h);
+                let symbolic = eval(&derivative, x);
+                assert!(
+                    (numeric - symbolic).abs() < 1e-4 * numeric.abs().max(1.0),
+                    "{raw} at {x}: {numeric} != {symbolic}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_nested_lets() {
+        // x^16 and x^(2^40), every binding is differentiated just once
+        let nested = |levels: usize| {
+            let lets = "let a = a * a in ".repeat(levels);
+            parse(&format!("let a = x * x in {lets}a")).unwrap()
+        };
+
+        let mut interpreter = crate::Interpreter::new();
+        interpreter.define_var("x".to_string(), 1.1);
+        let derivative = nested(3).derivative("x").unwrap();
+        let expected = 16.0 * 1.1f64.powi(15);
+        let actual = interpreter.evaluate(derivative).unwrap();
+        assert!((expected - actual).abs() < 1e-9, "{expected} != {actual}");
+
+        let derivative = nested(40).derivative("x").unwrap();
+        assert!(derivative.to_string().len() < 40 * 64);
+
+        // a binding of the same name hides the variable in the body only
+        assert!(!parse("let x = 1 in x").unwrap().mentions("x"));
+        assert!(parse("let x = x in x").unwrap().mentions("x"));
+        assert!(parse("let y = 1 in f(y, -x!)").unwrap().mentions("x"));
+    }
+
+    #[test]
+    fn test_unknown_derivative() {
+        let err = parse("1 + f(x)").unwrap().derivative("x").unwrap_err();
+        assert!(
+            matches!(err, Error::NoDerivative { ref callee, span } if callee == "f" && span == Span::new(4, 8))
+        );
+        let err = parse("x!").unwrap().derivative("x").unwrap_err();
+        assert!(matches!(err, Error::NoDerivative { ref callee, .. } if callee == "!"));
+        assert!(parse("3!").unwrap().derivative("x").is_ok());
+    }
+}
@@ -1,6 +1,64 @@
 use std::ops::Neg;
 
-use crate::{Error, Interpreter, Result, lexer::OperatorType};
+use crate::{
+    Error, Result,
+    interpreter::{Callee, Function, LazyArgs, Scope, call_error, with_args},
+    lexer::{OperatorType, Span, TokenType},
+    math,
+};
+
+use super::{Visitor, visit_node};
+
+/// Prefix operators, the ones that go right before an expression, like `-2`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum UnaryOp {
+    /// `+x`, leaves the value as is
+    PLUS,
+    /// `-x`, negates the value
+    MINUS,
+    /// `!x`, logical not, `1` if the value is zero and `0` otherwise
+    NOT,
+}
+
+impl UnaryOp {
+    /// Returns the prefix operator written with the given token, if any
+    pub fn from_token(token: &TokenType) -> Option<Self> {
+        match token {
+            TokenType::OPERATOR(OperatorType::PLUS) => Some(Self::PLUS),
+            TokenType::OPERATOR(OperatorType::MINUS) => Some(Self::MINUS),
+            TokenType::BANG => Some(Self::NOT),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let symbol = match self {
+            Self::PLUS => "+",
+            Self::MINUS => "-",
+            Self::NOT => "!",
+        };
+        write!(f, "{symbol}")
+    }
+}
+
+/// Postfix operators, the ones that go right after an expression, like `5!`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum PostfixOp {
+    /// `x!`, factorial generalised to real numbers via the gamma function
+    FACTORIAL,
+}
+
+impl std::fmt::Display for PostfixOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FACTORIAL => write!(f, "!"),
+        }
+    }
+}
 
 /// Expression type, represents... an expression.  
 /// It could be everything, from just a number like `2` till a function call
@@ -8,17 +66,28 @@ use crate::{Error, Interpreter, Result, lexer::OperatorType};
 /// lexper uses this one to recursively evaluate the "main" expression, the one you get from
 /// [`lexper::eval`][`crate::eval()`]. In that expression all other expressions are nested in and
 /// evaluated recursively.
-#[derive(Debug, PartialEq)]
+///
+/// Every variant carries a [`Span`] pointing to the part of the source it was parsed from.
+/// Spans are ignored when comparing expressions, so `2+3` and `2 + 3` are equal.
+#[derive(Debug, Clone)]
 pub enum Expression {
     /// Just a number, like "2"
-    Number(f64),
+    Number(f64, Span),
     /// Some variable inside an expression, like "PI"
-    Variable(String),
+    Variable(String, Span),
 
     /// Unary expression, like negotiation -> "-2"
     Unary {
-        op: OperatorType,
+        op: UnaryOp,
         expr: Box<Expression>,
+        span: Span,
+    },
+
+    /// Postfix expression, like factorial -> "5!"
+    Postfix {
+        op: PostfixOp,
+        expr: Box<Expression>,
+        span: Span,
     },
 
     /// Binary expression, the most common -> "2 + 3"
@@ -26,25 +95,152 @@ pub enum Expression {
         left: Box<Expression>,
         op: OperatorType,
         right: Box<Expression>,
+        span: Span,
+    },
+
+    /// Conditional expression, only one of the branches is evaluated -> "x < 0 ? -x : x"
+    Conditional {
+        condition: Box<Expression>,
+        then: Box<Expression>,
+        otherwise: Box<Expression>,
+        span: Span,
+    },
+
+    /// Binds a value to a name visible only in the body -> "let x = 2 in x * x"
+    Let {
+        name: String,
+        value: Box<Expression>,
+        body: Box<Expression>,
+        span: Span,
     },
 
     /// A function call -> "sin(2)"
     Call {
         callee: String,
         args: Vec<Expression>,
+        span: Span,
     },
 }
 
+impl PartialEq for Expression {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Number(a, _), Self::Number(b, _)) => a == b,
+            (Self::Variable(a, _), Self::Variable(b, _)) => a == b,
+            (
+                Self::Unary { op, expr, .. },
+                Self::Unary {
+                    op: other_op,
+                    expr: other_expr,
+                    ..
+                },
+            ) => op == other_op && expr == other_expr,
+            (
+                Self::Postfix { op, expr, .. },
+                Self::Postfix {
+                    op: other_op,
+                    expr: other_expr,
+                    ..
+                },
+            ) => op == other_op && expr == other_expr,
+            (
+                Self::Binary {
+                    left, op, right, ..
+                },
+                Self::Binary {
+                    left: other_left,
+                    op: other_op,
+                    right: other_right,
+                    ..
+                },
+            ) => op == other_op && left == other_left && right == other_right,
+            (
+                Self::Conditional {
+                    condition,
+                    then,
+                    otherwise,
+                    ..
+                },
+                Self::Conditional {
+                    condition: other_condition,
+                    then: other_then,
+                    otherwise: other_otherwise,
+                    ..
+                },
+            ) => condition == other_condition && then == other_then && otherwise == other_otherwise,
+            (
+                Self::Let {
+                    name, value, body, ..
+                },
+                Self::Let {
+                    name: other_name,
+                    value: other_value,
+                    body: other_body,
+                    ..
+                },
+            ) => name == other_name && value == other_value && body == other_body,
+            (
+                Self::Call { callee, args, .. },
+                Self::Call {
+                    callee: other_callee,
+                    args: other_args,
+                    ..
+                },
+            ) => callee == other_callee && args == other_args,
+            _ => false,
+        }
+    }
+}
+
 impl Expression {
-    fn apply_unary_op(op: &OperatorType, n: f64) -> Result<f64> {
+    /// Returns the byte range of the source this expression was parsed from
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Number(_, span) | Self::Variable(_, span) => *span,
+            Self::Unary { span, .. }
+            | Self::Postfix { span, .. }
+            | Self::Binary { span, .. }
+            | Self::Conditional { span, .. }
+            | Self::Let { span, .. }
+            | Self::Call { span, .. } => *span,
+        }
+    }
+
+    /// Replaces the span of this expression, used when a node gets wrapped into something
+    /// that has no node on its own, like parentheses
+    pub(crate) fn with_span(mut self, new_span: Span) -> Self {
+        match &mut self {
+            Self::Number(_, span) | Self::Variable(_, span) => *span = new_span,
+            Self::Unary { span, .. }
+            | Self::Postfix { span, .. }
+            | Self::Binary { span, .. }
+            | Self::Conditional { span, .. }
+            | Self::Let { span, .. }
+            | Self::Call { span, .. } => *span = new_span,
+        }
+        self
+    }
+
+    pub(crate) fn apply_unary_op(op: &UnaryOp, n: f64) -> f64 {
         match op {
-            OperatorType::PLUS => Ok(n.abs()),
-            OperatorType::MINUS => Ok(n.neg()),
-            _ => Err(Error::invalid_unary_op(op)),
+            UnaryOp::PLUS => n,
+            UnaryOp::MINUS => n.neg(),
+            UnaryOp::NOT => math::from_bool(!math::is_truthy(n)),
         }
     }
 
-    fn apply_binary_op(op: &OperatorType, lhs: f64, rhs: f64) -> f64 {
+    pub(crate) fn apply_postfix_op(op: &PostfixOp, n: f64, span: Span) -> Result<f64> {
+        match op {
+            PostfixOp::FACTORIAL => math::factorial(n).ok_or_else(|| {
+                Error::domain_error(
+                    format!("Factorial of a negative integer is undefined: {}!", n),
+                    span,
+                )
+            }),
+        }
+    }
+
+    pub(crate) fn apply_binary_op(op: &OperatorType, lhs: f64, rhs: f64) -> f64 {
         match op {
             OperatorType::PLUS => lhs + rhs,
             OperatorType::MINUS => lhs - rhs,
@@ -52,39 +248,119 @@ impl Expression {
             OperatorType::DIVIDE => lhs / rhs,
             OperatorType::MODULO => lhs % rhs,
             OperatorType::POWER => lhs.powf(rhs),
+            OperatorType::EQ => math::from_bool(lhs == rhs),
+            OperatorType::NE => math::from_bool(lhs != rhs),
+            OperatorType::LT => math::from_bool(lhs < rhs),
+            OperatorType::LE => math::from_bool(lhs <= rhs),
+            OperatorType::GT => math::from_bool(lhs > rhs),
+            OperatorType::GE => math::from_bool(lhs >= rhs),
+            OperatorType::AND => math::from_bool(math::is_truthy(lhs) && math::is_truthy(rhs)),
+            OperatorType::OR => math::from_bool(math::is_truthy(lhs) || math::is_truthy(rhs)),
         }
     }
 
-    pub(crate) fn eval(&self, ctx: &Interpreter) -> Result<f64> {
-        match self {
-            Self::Number(n) => Ok(*n),
-            Self::Variable(var) => {
-                let val = ctx.get_var(var);
-                if let Some(val) = val {
-                    Ok(val)
-                } else {
-                    panic!("Undefined variable '{}'", var);
-                }
-            }
-            Self::Unary { op, expr } => {
-                let res = expr.eval(ctx)?;
-                Ok(Self::apply_unary_op(op, res)?)
+    pub(crate) fn eval(&self, scope: &Scope) -> Result<f64> {
+        Evaluator { scope }.visit_expression(self)
+    }
+}
+
+/// Walks the tree and computes its value in the given scope
+struct Evaluator<'s> {
+    scope: &'s Scope<'s>,
+}
+
+impl<'a> Visitor<'a> for Evaluator<'_> {
+    type Output = Result<f64>;
+
+    fn visit_expression(&mut self, expr: &'a Expression) -> Result<f64> {
+        self.scope.interpreter().step(expr.span())?;
+        visit_node(self, expr)
+    }
+
+    fn visit_number(&mut self, value: f64, _span: Span) -> Result<f64> {
+        Ok(value)
+    }
+
+    fn visit_variable(&mut self, name: &'a str, span: Span) -> Result<f64> {
+        self.scope
+            .get_var(name)
+            .unwrap_or_else(|| Err(Error::undefined(name.to_owned(), span)))
+    }
+
+    fn visit_unary(&mut self, op: UnaryOp, expr: &'a Expression, _span: Span) -> Result<f64> {
+        let res = self.visit_expression(expr)?;
+        Ok(Expression::apply_unary_op(&op, res))
+    }
+
+    fn visit_postfix(&mut self, op: PostfixOp, expr: &'a Expression, span: Span) -> Result<f64> {
+        let res = self.visit_expression(expr)?;
+        Expression::apply_postfix_op(&op, res, span)
+    }
+
+    fn visit_binary(
+        &mut self,
+        left: &'a Expression,
+        op: &'a OperatorType,
+        right: &'a Expression,
+        _span: Span,
+    ) -> Result<f64> {
+        let left = self.visit_expression(left)?;
+
+        // logical operators don't evaluate the right side if the left one decides already
+        match op {
+            OperatorType::AND if !math::is_truthy(left) => return Ok(0.0),
+            OperatorType::OR if math::is_truthy(left) => return Ok(1.0),
+            _ => {}
+        }
+
+        let right = self.visit_expression(right)?;
+        Ok(Expression::apply_binary_op(op, left, right))
+    }
+
+    fn visit_conditional(
+        &mut self,
+        condition: &'a Expression,
+        then: &'a Expression,
+        otherwise: &'a Expression,
+        _span: Span,
+    ) -> Result<f64> {
+        if math::is_truthy(self.visit_expression(condition)?) {
+            self.visit_expression(then)
+        } else {
+            self.visit_expression(otherwise)
+        }
+    }
+
+    fn visit_let(
+        &mut self,
+        name: &'a str,
+        value: &'a Expression,
+        body: &'a Expression,
+        _span: Span,
+    ) -> Result<f64> {
+        let value = self.visit_expression(value)?;
+        Evaluator {
+            scope: &self.scope.child(name, value),
+        }
+        .visit_expression(body)
+    }
+
+    fn visit_call(&mut self, callee: &'a str, args: &'a [Expression], span: Span) -> Result<f64> {
+        let ctx = self.scope.interpreter();
+        let eval = |arg: &'a Expression| Evaluator { scope: self.scope }.visit_expression(arg);
+
+        let result = match ctx.get_callee(callee, args.len(), span)? {
+            Callee::Function(Function::Native(function)) => with_args(args, eval, function),
+            Callee::Function(Function::Lazy(function)) => {
+                function(&LazyArgs::new(self.scope, args))
             }
-            Self::Binary { left, op, right } => {
-                let left = left.eval(ctx)?;
-                let right = right.eval(ctx)?;
-                Ok(Self::apply_binary_op(op, left, right))
+            Callee::Function(Function::User(function)) => {
+                with_args(args, eval, |values| ctx.call_user(function, values, span))
             }
-            Self::Call { callee, args } => {
-                let mut evaluated_args = Vec::with_capacity(args.len());
-                for arg in args {
-                    let value = arg.eval(ctx)?;
-                    evaluated_args.push(value);
-                }
-
-                ctx.call_fn(callee.as_str(), evaluated_args.as_slice())
-                    .ok_or(Error::undefined(callee.to_owned()))?
+            Callee::Resolver(resolver) => {
+                with_args(args, eval, |values| resolver.call(callee, values))
             }
-        }
+        };
+        result.map_err(call_error(callee, span))
     }
 }
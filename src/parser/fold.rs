@@ -0,0 +1,227 @@
+use crate::lexer::{OperatorType, Span};
+
+use super::{Expression, PostfixOp, UnaryOp};
+
+/// Rebuilds an [`Expression`], taking it by value. Every method folds the children of its node
+/// and puts the node back together by default, so a pass overrides only the nodes it rewrites.
+///
+/// ## Example usage
+/// ```
+/// use lexper::{Expression, Folder, Lexer, Parser, Span};
+///
+/// /// Renames a variable, the bodies of `let`s shadowing it are left alone
+/// struct Rename;
+///
+/// impl Folder for Rename {
+///     fn fold_variable(&mut self, name: String, span: Span) -> Expression {
+///         let name = if name == "t" { "time".to_string() } else { name };
+///         Expression::Variable(name, span)
+///     }
+///
+///     fn fold_let(
+///         &mut self,
+///         name: String,
+///         value: Box<Expression>,
+///         body: Box<Expression>,
+///         span: Span,
+///     ) -> Expression {
+///         let value = self.fold_boxed(value);
+///         // the body sees the binding instead of the variable
+///         let body = if name == "t" { body } else { self.fold_boxed(body) };
+///         Expression::Let { name, value, body, span }
+///     }
+/// }
+///
+/// let mut lexer = Lexer::new("2 * t + sin(let t = t + 1 in t)".to_string());
+/// lexer.lex().unwrap();
+/// let expr = Parser::new(lexer.tokens()).parse().unwrap();
+/// assert_eq!(
+///     Rename.fold_expression(expr).to_string(),
+///     "2 * time + sin(let t = time + 1 in t)"
+/// );
+/// ```
+pub trait Folder {
+    /// Folds any expression, calls the method of its node through [`fold_node`]. Override it to
+    /// do something around every node.
+    fn fold_expression(&mut self, expr: Expression) -> Expression {
+        fold_node(self, expr)
+    }
+
+    fn fold_number(&mut self, value: f64, span: Span) -> Expression {
+        Expression::Number(value, span)
+    }
+
+    fn fold_variable(&mut self, name: String, span: Span) -> Expression {
+        Expression::Variable(name, span)
+    }
+
+    fn fold_unary(&mut self, op: UnaryOp, expr: Box<Expression>, span: Span) -> Expression {
+        Expression::Unary {
+            op,
+            expr: self.fold_boxed(expr),
+            span,
+        }
+    }
+
+    fn fold_postfix(&mut self, op: PostfixOp, expr: Box<Expression>, span: Span) -> Expression {
+        Expression::Postfix {
+            op,
+            expr: self.fold_boxed(expr),
+            span,
+        }
+    }
+
+    fn fold_binary(
+        &mut self,
+        left: Box<Expression>,
+        op: OperatorType,
+        right: Box<Expression>,
+        span: Span,
+    ) -> Expression {
+        Expression::Binary {
+            left: self.fold_boxed(left),
+            op,
+            right: self.fold_boxed(right),
+            span,
+        }
+    }
+
+    fn fold_conditional(
+        &mut self,
+        condition: Box<Expression>,
+        then: Box<Expression>,
+        otherwise: Box<Expression>,
+        span: Span,
+    ) -> Expression {
+        Expression::Conditional {
+            condition: self.fold_boxed(condition),
+            then: self.fold_boxed(then),
+            otherwise: self.fold_boxed(otherwise),
+            span,
+        }
+    }
+
+    /// The binding is folded before the body
+    fn fold_let(
+        &mut self,
+        name: String,
+        value: Box<Expression>,
+        body: Box<Expression>,
+        span: Span,
+    ) -> Expression {
+        Expression::Let {
+            name,
+            value: self.fold_boxed(value),
+            body: self.fold_boxed(body),
+            span,
+        }
+    }
+
+    fn fold_call(&mut self, callee: String, args: Vec<Expression>, span: Span) -> Expression {
+        Expression::Call {
+            callee,
+            args: args
+                .into_iter()
+                .map(|arg| self.fold_expression(arg))
+                .collect(),
+            span,
+        }
+    }
+
+    /// Folds a boxed child in place, so its allocation is reused
+    fn fold_boxed(&mut self, mut expr: Box<Expression>) -> Box<Expression> {
+        *expr = self.fold_expression(*expr);
+        expr
+    }
+}
+
+/// Calls the method of the folder matching the node of the expression, it's what
+/// [`Folder::fold_expression`] does by default
+pub fn fold_node<F: Folder + ?Sized>(folder: &mut F, expr: Expression) -> Expression {
+    match expr {
+        Expression::Number(value, span) => folder.fold_number(value, span),
+        Expression::Variable(name, span) => folder.fold_variable(name, span),
+        Expression::Unary { op, expr, span } => folder.fold_unary(op, expr, span),
+        Expression::Postfix { op, expr, span } => folder.fold_postfix(op, expr, span),
+        Expression::Binary {
+            left,
+            op,
+            right,
+            span,
+        } => folder.fold_binary(left, op, right, span),
+        Expression::Conditional {
+            condition,
+            then,
+            otherwise,
+            span,
+        } => folder.fold_conditional(condition, then, otherwise, span),
+        Expression::Let {
+            name,
+            value,
+            body,
+            span,
+        } => folder.fold_let(name, value, body, span),
+        Expression::Call { callee, args, span } => folder.fold_call(callee, args, span),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Result;
+
+    fn parse(raw: &str) -> Result<Expression> {
+        let mut lexer = crate::Lexer::new(raw.to_string());
+        lexer.lex()?;
+        crate::Parser::new(lexer.tokens()).parse()
+    }
+
+    #[test]
+    fn test_default_folder_keeps_the_tree() {
+        struct Identity;
+
+        impl Folder for Identity {}
+
+        let raw = "let a = max(1, -x) in a > 0 ? sqrt(a)! : f() - 2^a";
+        let expr = parse(raw).unwrap();
+        let folded = Identity.fold_expression(expr.clone());
+        assert_eq!(folded, expr);
+        assert_eq!(folded.span(), expr.span());
+    }
+
+    #[test]
+    fn test_folder_rewrites() {
+        /// Writes `x^2` as `x * x`, bottom up
+        struct SquareToProduct;
+
+        impl Folder for SquareToProduct {
+            fn fold_binary(
+                &mut self,
+                left: Box<Expression>,
+                op: OperatorType,
+                right: Box<Expression>,
+                span: Span,
+            ) -> Expression {
+                let (left, right) = (self.fold_boxed(left), self.fold_boxed(right));
+                match (&op, &*right) {
+                    (OperatorType::POWER, Expression::Number(2.0, _)) => Expression::Binary {
+                        left: left.clone(),
+                        op: OperatorType::MULTIPLY,
+                        right: left,
+                        span,
+                    },
+                    _ => Expression::Binary {
+                        left,
+                        op,
+                        right,
+                        span,
+                    },
+                }
+            }
+        }
+
+        let expr = parse("(a + 1)^2 + sin(b^2)^3").unwrap();
+        let expected = parse("(a + 1) * (a + 1) + sin(b * b)^3").unwrap();
+        assert_eq!(SquareToProduct.fold_expression(expr), expected);
+    }
+}
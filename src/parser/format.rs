@@ -0,0 +1,507 @@
+use std::fmt::Write;
+
+use crate::lexer::{Associativity, OperatorType, PREFIX_PRECEDENCE, Span, TERNARY_PRECEDENCE};
+
+use super::Expression;
+
+/// Precedence of postfix operators, they bind tighter than any binary one, so `2^3!` is
+/// `2^(3!)`
+pub(super) const POSTFIX_PRECEDENCE: u8 = 9;
+
+/// Precedence of numbers, variables and calls, they never need parentheses
+pub(super) const ATOM_PRECEDENCE: u8 = 10;
+
+/// Tells [`Expression::format`] how to write an expression, the default is what [`Display`]
+/// writes, like `2 * x + max(y, 1)`.
+///
+/// [`Display`]: std::fmt::Display
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FormatOptions {
+    /// Leaves out the spaces around binary operators, `?`, `:` and after commas, `2*x+max(y,1)`
+    pub compact: bool,
+    /// Leaves out the `*` between a number and a variable, a call or a group in parentheses,
+    /// `2x`, `3sin(x)`, `2(x + 1)`. Such a text has to be parsed with
+    /// [`implicit_multiplication`][`super::ParserOptions::implicit_multiplication`] and the names
+    /// of the called functions to get the same expression back.
+    pub implicit_multiplication: bool,
+}
+
+impl Expression {
+    /// Writes the expression back as text, with parentheses only where the precedence or the
+    /// associativity of the operators requires them. Parsing the text gives the same expression,
+    /// spans aside.
+    ///
+    /// Numbers the parser never produces are written as expressions of the same value, so
+    /// they come back as those expressions: negative ones like `-2`, infinity like `1 / 0` and
+    /// NaN like `0 / 0`.
+    ///
+    /// ## Example usage
+    /// ```
+    /// use lexper::{FormatOptions, Lexer, Parser};
+    ///
+    /// let mut lexer = Lexer::new("((2 * (x)) + y ^ (2 ^ 3))".to_string());
+    /// lexer.lex().unwrap();
+    /// let expr = Parser::new(lexer.tokens()).parse().unwrap();
+    ///
+    /// assert_eq!(expr.to_string(), "2 * x + y ^ 2 ^ 3");
+    /// let options = FormatOptions {
+    ///     compact: true,
+    ///     implicit_multiplication: true,
+    /// };
+    /// assert_eq!(expr.format(&options), "2x+y^2^3");
+    /// ```
+    pub fn format(&self, options: &FormatOptions) -> String {
+        let mut printer = Printer::new(*options);
+        printer.expr(self, TERNARY_PRECEDENCE);
+        printer.out
+    }
+
+    /// How tight the expression binds, it needs parentheses where a tighter one is expected
+    fn precedence(&self) -> u8 {
+        match self {
+            Self::Number(n, _) if !n.is_finite() => OperatorType::DIVIDE.precedence(),
+            Self::Number(n, _) if n.is_sign_negative() => PREFIX_PRECEDENCE,
+            Self::Number(..) | Self::Variable(..) | Self::Call { .. } => ATOM_PRECEDENCE,
+            Self::Unary { .. } => PREFIX_PRECEDENCE,
+            Self::Postfix { .. } => POSTFIX_PRECEDENCE,
+            Self::Binary { op, .. } => op.precedence(),
+            // both extend as far to the right as possible
+            Self::Conditional { .. } | Self::Let { .. } => TERNARY_PRECEDENCE,
+        }
+    }
+}
+
+impl std::fmt::Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.format(&FormatOptions::default()))
+    }
+}
+
+struct Printer {
+    options: FormatOptions,
+    out: String,
+}
+
+impl Printer {
+    fn new(options: FormatOptions) -> Self {
+        Self {
+            options,
+            out: String::new(),
+        }
+    }
+
+    /// Writes the expression, in parentheses if it binds looser than `min_prec`
+    fn expr(&mut self, expr: &Expression, min_prec: u8) {
+        if expr.precedence() < min_prec {
+            self.out.push('(');
+            self.node(expr);
+            self.out.push(')');
+        } else {
+            self.node(expr);
+        }
+    }
+
+    /// Writes an operand nothing follows in its own expression. A prefix operator takes only
+    /// what binds tighter than itself, so it needs no parentheses there, like in `2 ^ -x`.
+    fn trailing(&mut self, expr: &Expression, min_prec: u8) {
+        self.expr(expr, trailing_precedence(expr, min_prec))
+    }
+
+    /// Writes a separator, with spaces around it unless the output is compact
+    fn separator(&mut self, symbol: &str) {
+        if self.options.compact {
+            self.out.push_str(symbol);
+        } else {
+            write!(self.out, " {symbol} ").unwrap();
+        }
+    }
+
+    fn node(&mut self, expr: &Expression) {
+        match expr {
+            Expression::Number(n, _) if !n.is_finite() => self.node(&non_finite(*n)),
+            Expression::Number(n, _) => self.out.push_str(&number(*n)),
+            Expression::Variable(name, _) => self.out.push_str(name),
+            Expression::Unary { op, expr, .. } => {
+                write!(self.out, "{op}").unwrap();
+                self.trailing(expr, PREFIX_PRECEDENCE);
+            }
+            Expression::Postfix { op, expr, .. } => {
+                self.expr(expr, POSTFIX_PRECEDENCE);
+                write!(self.out, "{op}").unwrap();
+            }
+            Expression::Binary {
+                left, op, right, ..
+            } => {
+                let prec = op.precedence();
+                let (left_prec, right_prec) = match op.associativity() {
+                    Associativity::Left => (prec, prec + 1),
+                    Associativity::Right => (prec + 1, prec),
+                };
+                self.expr(left, left_prec);
+
+                let right_prec = trailing_precedence(right, right_prec);
+                if *op == OperatorType::MULTIPLY
+                    && self.is_implicit(left, first_char(right, right_prec))
+                {
+                    // nothing in between
+                } else if self.options.compact && *op == OperatorType::EQ && self.out.ends_with('!')
+                {
+                    // `x!==y` would be read as `x != =y`
+                    write!(self.out, " {op}").unwrap();
+                } else {
+                    self.separator(&op.to_string());
+                }
+                self.expr(right, right_prec);
+            }
+            Expression::Conditional {
+                condition,
+                then,
+                otherwise,
+                ..
+            } => {
+                // a nested conditional or `let` as the condition would take the `?` as its own
+                self.expr(condition, TERNARY_PRECEDENCE + 1);
+                self.separator("?");
+                self.expr(then, TERNARY_PRECEDENCE);
+                self.separator(":");
+                self.expr(otherwise, TERNARY_PRECEDENCE);
+            }
+            Expression::Let {
+                name, value, body, ..
+            } => {
+                write!(self.out, "let {name}").unwrap();
+                self.separator("=");
+                self.expr(value, TERNARY_PRECEDENCE);
+                self.out.push_str(" in ");
+                self.expr(body, TERNARY_PRECEDENCE);
+            }
+            Expression::Call { callee, args, .. } => {
+                write!(self.out, "{callee}(").unwrap();
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        self.out
+                            .push_str(if self.options.compact { "," } else { ", " });
+                    }
+                    self.expr(arg, TERNARY_PRECEDENCE);
+                }
+                self.out.push(')');
+            }
+        }
+    }
+
+    /// Checks if the multiplication of `left` by a right side starting with `right` can go
+    /// without the `*`, the lexer has to read them as two tokens again
+    fn is_implicit(&self, left: &Expression, right: char) -> bool {
+        if !self.options.implicit_multiplication {
+            return false;
+        }
+        let Expression::Number(n, _) = left else {
+            return false;
+        };
+        let left = number(*n);
+        if left.contains(['e', '-']) || !n.is_finite() {
+            return false;
+        }
+
+        match right {
+            '(' => true,
+            // `2e` starts an exponent and `0x` a hex number
+            c => c.is_ascii_alphabetic() && !matches!(c, 'e' | 'E') && left != "0",
+        }
+    }
+}
+
+/// The precedence an operand nothing follows is written with, see [`Printer::trailing`]
+fn trailing_precedence(expr: &Expression, min_prec: u8) -> u8 {
+    min_prec.min(expr.precedence().max(PREFIX_PRECEDENCE))
+}
+
+/// Returns the first char [`Printer::expr`] writes for the expression, without writing it.
+/// Only the leftmost operands are walked, so a right side isn't written twice.
+fn first_char(expr: &Expression, min_prec: u8) -> char {
+    let mut expr = expr;
+    let mut min_prec = min_prec;
+    loop {
+        if expr.precedence() < min_prec {
+            return '(';
+        }
+        (expr, min_prec) = match expr {
+            Expression::Number(n, _) if n.is_nan() => return '0',
+            // `1 / 0` or `-1 / 0`
+            Expression::Number(n, _) if n.is_infinite() => {
+                return if *n > 0.0 { '1' } else { '-' };
+            }
+            Expression::Number(n, _) if n.is_sign_negative() => return '-',
+            Expression::Number(n, _) => return number(*n).chars().next().unwrap_or('0'),
+            Expression::Variable(name, _) | Expression::Call { callee: name, .. } => {
+                return name.chars().next().unwrap_or('(');
+            }
+            Expression::Unary { op, .. } => return op.to_string().chars().next().unwrap_or('('),
+            Expression::Let { .. } => return 'l',
+            Expression::Postfix { expr, .. } => (&**expr, POSTFIX_PRECEDENCE),
+            Expression::Binary { left, op, .. } => {
+                let left_prec = match op.associativity() {
+                    Associativity::Left => op.precedence(),
+                    Associativity::Right => op.precedence() + 1,
+                };
+                (&**left, left_prec)
+            }
+            Expression::Conditional { condition, .. } => (&**condition, TERNARY_PRECEDENCE + 1),
+        };
+    }
+}
+
+/// Writes a finite number the way the lexer reads it back as the same value
+pub(super) fn number(n: f64) -> String {
+    let abs = n.abs();
+    if abs != 0.0 && !(1e-5..1e16).contains(&abs) {
+        format!("{n:e}")
+    } else {
+        format!("{n}")
+    }
+}
+
+/// An expression evaluating to the infinite or NaN number
+fn non_finite(n: f64) -> Expression {
+    let numerator = if n.is_nan() { 0.0 } else { n.signum() };
+    let span = Span::default();
+    Expression::Binary {
+        left: Box::new(Expression::Number(numerator, span)),
+        op: OperatorType::DIVIDE,
+        right: Box::new(Expression::Number(0.0, span)),
+        span,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ParserOptions, PostfixOp, Result, UnaryOp};
+
+    fn parse_with(raw: &str, options: ParserOptions) -> Result<Expression> {
+        let mut lexer = crate::Lexer::new(raw.to_string());
+        lexer.lex()?;
+        crate::Parser::with_options(lexer.tokens(), options).parse()
+    }
+
+    fn parse(raw: &str) -> Result<Expression> {
+        parse_with(raw, ParserOptions::default())
+    }
+
+    #[test]
+    fn test_display() {
+        let cases = [
+            ("(1 + 2) + 3", "1 + 2 + 3"),
+            ("1 + (2 + 3)", "1 + (2 + 3)"),
+            ("1 - (2 - 3)", "1 - (2 - 3)"),
+            ("(2 ^ 3) ^ 4", "(2 ^ 3) ^ 4"),
+            ("2 ^ (3 ^ 4)", "2 ^ 3 ^ 4"),
+            ("(-2) ^ 2", "(-2) ^ 2"),
+            ("-(2 ^ 2)", "-2 ^ 2"),
+            ("2 ^ (-x) * 3", "2 ^ -x * 3"),
+            ("a - (-b)", "a - -b"),
+            ("(x!)! + (-x)! + (x ^ 2)!", "x!! + (-x)! + (x ^ 2)!"),
+            ("!(a && b) || c", "!(a && b) || c"),
+            ("(a < b) == (c >= d)", "a < b == c >= d"),
+            (
+                "(a ? b : c) ? (d ? e : f) : (g ? h : i)",
+                "(a ? b : c) ? d ? e : f : g ? h : i",
+            ),
+            ("1 + (x > 0 ? x : 0)", "1 + (x > 0 ? x : 0)"),
+            (
+                "(let a = 1 in a) + (let b = 2 in b)",
+                "(let a = 1 in a) + (let b = 2 in b)",
+            ),
+            (
+                "let a = (let b = 1 in b) in a",
+                "let a = let b = 1 in b in a",
+            ),
+            ("max((1), -(2), sin(x + 1))", "max(1, -2, sin(x + 1))"),
+            ("0.1 + 1e21 + 2.5e-7 + 1_000", "0.1 + 1e21 + 2.5e-7 + 1000"),
+        ];
+        for (raw, expected) in cases {
+            let expr = parse(raw).unwrap();
+            assert_eq!(expr.to_string(), expected, "{raw}");
+            assert_eq!(parse(expected).unwrap(), expr, "{raw}");
+        }
+    }
+
+    #[test]
+    fn test_format_options() {
+        let compact = FormatOptions {
+            compact: true,
+            ..FormatOptions::default()
+        };
+        let implicit = FormatOptions {
+            implicit_multiplication: true,
+            ..FormatOptions::default()
+        };
+        let both = FormatOptions {
+            compact: true,
+            implicit_multiplication: true,
+        };
+        let cases = [
+            ("x! == y", &compact, "x! ==y"),
+            ("x > 0 ? max(x, 1) : -x", &compact, "x>0?max(x,1):-x"),
+            ("let a = 2 * x in a", &compact, "let a=2*x in a"),
+            (
+                "2 * x * y + 3 * sin(x) + 2 * (x + 1)",
+                &implicit,
+                "2x * y + 3sin(x) + 2(x + 1)",
+            ),
+            (
+                "2 * exp(x) + 0 * x + x * 2 + 2 * -x",
+                &implicit,
+                "2 * exp(x) + 0 * x + x * 2 + 2 * -x",
+            ),
+            ("2 * x ^ 2 - 1e20 * x", &both, "2x^2-1e20*x"),
+        ];
+        for (raw, options, expected) in cases {
+            let expr = parse(raw).unwrap();
+            let formatted = expr.format(options);
+            assert_eq!(formatted, expected, "{raw}");
+
+            let parser_options = ParserOptions {
+                implicit_multiplication: options.implicit_multiplication,
+                functions: Some(["sin", "max", "exp"].map(String::from).into()),
+                ..ParserOptions::default()
+            };
+            assert_eq!(
+                parse_with(&formatted, parser_options).unwrap(),
+                expr,
+                "{raw}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_display_numbers() {
+        let span = Span::default();
+        let number = |n: f64| Expression::Number(n, span);
+        let expr = Expression::Binary {
+            left: Box::new(number(-1.0)),
+            op: OperatorType::POWER,
+            right: Box::new(number(f64::INFINITY)),
+            span,
+        };
+        assert_eq!(expr.to_string(), "(-1) ^ (1 / 0)");
+        assert_eq!(number(f64::NAN).to_string(), "0 / 0");
+        assert_eq!(number(f64::NEG_INFINITY).to_string(), "-1 / 0");
+
+        for n in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, -0.0, -2.0, 1e30] {
+            for min_prec in [TERNARY_PRECEDENCE, ATOM_PRECEDENCE] {
+                let mut printer = Printer::new(FormatOptions::default());
+                printer.expr(&number(n), min_prec);
+                assert_eq!(
+                    Some(first_char(&number(n), min_prec)),
+                    printer.out.chars().next()
+                );
+            }
+        }
+    }
+
+    /// Builds random expressions the parser could produce
+    struct Generator(u64);
+
+    impl Generator {
+        fn next(&mut self, n: u64) -> u64 {
+            // xorshift, good enough to get varied trees
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+
+        fn expr(&mut self, depth: u32) -> Expression {
+            let span = Span::default();
+            let boxed = |g: &mut Self| Box::new(g.expr(depth - 1));
+            let kind = if depth == 0 {
+                self.next(3)
+            } else {
+                self.next(10)
+            };
+            match kind {
+                0 => Expression::Number([0.0, 2.0, 0.5, 1e30][self.next(4) as usize], span),
+                1 => Expression::Variable(["x", "y", "e1"][self.next(3) as usize].into(), span),
+                2 => Expression::Call {
+                    callee: "f".into(),
+                    args: (0..self.next(3))
+                        .map(|_| self.expr(depth.saturating_sub(1)))
+                        .collect(),
+                    span,
+                },
+                3 => Expression::Unary {
+                    op: [UnaryOp::MINUS, UnaryOp::NOT][self.next(2) as usize],
+                    expr: boxed(self),
+                    span,
+                },
+                4 => Expression::Postfix {
+                    op: PostfixOp::FACTORIAL,
+                    expr: boxed(self),
+                    span,
+                },
+                5 => Expression::Conditional {
+                    condition: boxed(self),
+                    then: boxed(self),
+                    otherwise: boxed(self),
+                    span,
+                },
+                6 => Expression::Let {
+                    name: "a".into(),
+                    value: boxed(self),
+                    body: boxed(self),
+                    span,
+                },
+                _ => {
+                    let ops = [
+                        OperatorType::PLUS,
+                        OperatorType::MINUS,
+                        OperatorType::MULTIPLY,
+                        OperatorType::DIVIDE,
+                        OperatorType::POWER,
+                        OperatorType::EQ,
+                        OperatorType::LT,
+                        OperatorType::AND,
+                        OperatorType::OR,
+                    ];
+                    Expression::Binary {
+                        left: boxed(self),
+                        op: ops[self.next(ops.len() as u64) as usize].clone(),
+                        right: boxed(self),
+                        span,
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut generator = Generator(0x2545_f491_4f6c_dd1d);
+        let options = [
+            FormatOptions::default(),
+            FormatOptions {
+                compact: true,
+                implicit_multiplication: true,
+            },
+        ];
+        for _ in 0..2000 {
+            let expr = generator.expr(5);
+            for options in &options {
+                let formatted = expr.format(options);
+                let parser_options = ParserOptions {
+                    implicit_multiplication: options.implicit_multiplication,
+                    functions: Some(["f".to_string()].into()),
+                    ..ParserOptions::default()
+                };
+                let parsed = parse_with(&formatted, parser_options);
+                assert_eq!(parsed.ok(), Some(expr.clone()), "{formatted}");
+                assert_eq!(
+                    Some(first_char(&expr, TERNARY_PRECEDENCE)),
+                    formatted.chars().next(),
+                    "{formatted}"
+                );
+            }
+        }
+    }
+}
@@ -1,106 +1,291 @@
-use crate::lexer::{OperatorType, Token, TokenType};
-use crate::{Error, Result};
+use std::collections::HashSet;
 
+use crate::lexer::{
+    Associativity, OperatorType, PREFIX_PRECEDENCE, Span, TERNARY_PRECEDENCE, Token, TokenType,
+};
+use crate::{Error, Limits, Result};
+
+mod derivative;
 mod expression;
-pub use expression::Expression;
+mod fold;
+mod format;
+mod render;
+mod statement;
+mod visit;
+pub use expression::{Expression, PostfixOp, UnaryOp};
+pub use fold::{Folder, fold_node};
+pub use format::FormatOptions;
+pub use render::{IdentifierRenderer, Markup};
+pub use statement::{Program, Statement};
+pub use visit::{VisitOutput, Visitor, visit_all, visit_node};
 
 /// The heart struct of the lexper. It parses the token vector into a nested AST of [`Expression`]s.
 pub struct Parser<'a> {
     tokens: &'a [Token],
     pos: usize,
+    /// How many parentheses are open right now, line breaks inside of them are ignored
+    depth: usize,
+    /// Line breaks separate statements, only while parsing a [`Program`]. Otherwise they're
+    /// just whitespace.
+    statements: bool,
+    /// How deep the expression being parsed nests right now
+    nesting: usize,
+    /// How many nodes were parsed so far
+    nodes: usize,
+    options: ParserOptions,
+}
+
+/// Optional behaviour of the [`Parser`], everything is disabled by default
+///
+/// ## Example usage
+/// ```
+/// use lexper::{Interpreter, Lexer, Parser, ParserOptions};
+///
+/// let mut interpreter = Interpreter::new();
+/// interpreter.define_var("a".to_string(), 3.0);
+///
+/// let options = ParserOptions {
+///     implicit_multiplication: true,
+///     functions: Some(interpreter.function_names().map(String::from).collect()),
+///     ..ParserOptions::default()
+/// };
+///
+/// let mut lexer = Lexer::new("2a(a + 1) + 2 sin(PI)".to_string());
+/// lexer.lex().unwrap();
+/// let expr = Parser::with_options(lexer.tokens(), options).parse().unwrap();
+/// assert_eq!(interpreter.evaluate(expr).unwrap().round(), 24.0);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ParserOptions {
+    /// Treats terms written next to each other as multiplied, `2PI`, `3(4 + 1)`, `(a+1)(a-1)`.
+    /// A number or `)` can be followed by a number, an identifier or `(`, and an identifier
+    /// which is not a function can be followed by `(`.
+    pub implicit_multiplication: bool,
+    /// Names of functions, used to tell a call `f(x)` from a multiplication `a(x)` when
+    /// [`implicit_multiplication`][`ParserOptions::implicit_multiplication`] is enabled.
+    /// `None` means every identifier followed by `(` is a call.
+    pub functions: Option<HashSet<String>>,
+    /// The parser checks [`max_depth`][`Limits::max_depth`] and
+    /// [`max_nodes`][`Limits::max_nodes`]
+    pub limits: Limits,
 }
 
 impl<'a> Parser<'a> {
     /// Creates a new instance of the [`Parser`], but as the argument requires a slice of tokens.
     /// This one was made to not to clone a vector of tokens after lexing.
     pub fn new(tokens: &'a [Token]) -> Self {
-        Self { tokens, pos: 0 }
+        Self::with_options(tokens, ParserOptions::default())
     }
 
-    fn peek(&self) -> Option<&Token> {
-        self.tokens.get(self.pos)
+    /// Creates a new instance of the [`Parser`] with provided options
+    pub fn with_options(tokens: &'a [Token], options: ParserOptions) -> Self {
+        Self {
+            tokens,
+            pos: 0,
+            depth: 0,
+            statements: false,
+            nesting: 0,
+            nodes: 0,
+            options,
+        }
     }
 
-    fn advance(&mut self) -> Option<&Token> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.next_pos())
+    }
+
+    fn advance(&mut self) -> Option<&'a Token> {
+        self.pos = self.next_pos();
         let token = self.tokens.get(self.pos);
         self.pos += 1;
         token
     }
 
-    fn expect_token(&mut self, expected: TokenType) -> Result<&Token> {
-        if let Some(token) = self.advance() {
-            if token.ty() == &expected {
-                Ok(token)
-            } else {
-                Err(Error::unexpected_value(
-                    &format!("{:?}", expected),
-                    Some(&format!("{:?}", token.get_type())),
-                ))
+    /// Position of the next meaningful token, line breaks mean nothing inside of parentheses or
+    /// outside of a program
+    fn next_pos(&self) -> usize {
+        let mut pos = self.pos;
+        if self.depth > 0 || !self.statements {
+            while let Some(TokenType::NEWLINE) = self.tokens.get(pos).map(|t| t.ty()) {
+                pos += 1;
             }
-        } else {
-            Err(Error::unexpected_value(&format!("{:?}", expected), None))
         }
+        pos
     }
 
-    pub(crate) fn parse_primary(&mut self) -> Result<Expression> {
-        if let Some(t) = self.peek() {
-            if let TokenType::OPERATOR(op) = t.get_type() {
-                if matches!(op, OperatorType::PLUS | OperatorType::MINUS) {
-                    self.advance();
-                    let expr = self.parse_primary();
-                    return Ok(Expression::Unary {
-                        op,
-                        expr: Box::new(expr?),
-                    });
-                }
+    /// Skips line breaks even outside of parentheses, used where a statement can't end
+    fn skip_newlines(&mut self) {
+        while let Some(TokenType::NEWLINE) = self.tokens.get(self.pos).map(|t| t.ty()) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect_token(&mut self, expected: TokenType) -> Result<&'a Token> {
+        match self.advance() {
+            Some(token) if token.ty() == &expected => Ok(token),
+            token => Err(self.unexpected(&expected.to_string(), token)),
+        }
+    }
+
+    /// Builds an error for a token that doesn't fit here, `None` means the input has ended
+    fn unexpected(&self, expected: &str, token: Option<&Token>) -> Error {
+        match token {
+            Some(token) => {
+                Error::unexpected_value(expected, Some(&token.ty().to_string()), token.span())
             }
+            None => Error::unexpected_value(expected, None, self.eof_span()),
+        }
+    }
+
+    /// An empty span right after the last token
+    fn eof_span(&self) -> Span {
+        let end = self.tokens.last().map(|t| t.span().end).unwrap_or(0);
+        Span::new(end, end)
+    }
+
+    /// Span of the current token, or of the end of input if there are no tokens left
+    fn current_span(&self) -> Span {
+        self.peek()
+            .map(|t| t.span())
+            .unwrap_or_else(|| self.eof_span())
+    }
+
+    /// Counts a new node of the AST, fails if there are too many of them
+    fn count_node(&mut self, span: Span) -> Result<()> {
+        self.nodes += 1;
+        if self.nodes > self.options.limits.max_nodes {
+            return Err(Error::too_many_nodes(self.options.limits.max_nodes, span));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn parse_primary(&mut self) -> Result<Expression> {
+        // an operand is expected, so a line break here can't end the statement, like in `2 +\n3`
+        self.skip_newlines();
+
+        if let Some(t) = self.peek()
+            && let Some(op) = UnaryOp::from_token(t.ty())
+        {
+            let op_span = t.span();
+            self.count_node(op_span)?;
+            self.advance();
+            let expr = self.parse_expression(PREFIX_PRECEDENCE)?;
+            return Ok(Expression::Unary {
+                op,
+                span: op_span.to(expr.span()),
+                expr: Box::new(expr),
+            });
         }
 
+        let atom = self.parse_atom()?;
+        self.parse_postfix(atom)
+    }
+
+    /// Wraps the expression into every postfix operator following it, like `3!!`
+    fn parse_postfix(&mut self, mut expr: Expression) -> Result<Expression> {
+        while let Some(t) = self.peek()
+            && let TokenType::BANG = t.ty()
+        {
+            self.count_node(t.span())?;
+            self.advance();
+            expr = Expression::Postfix {
+                op: PostfixOp::FACTORIAL,
+                span: expr.span().to(t.span()),
+                expr: Box::new(expr),
+            };
+        }
+
+        Ok(expr)
+    }
+
+    /// Parses a number, a variable, a call or a whole expression in parentheses
+    fn parse_atom(&mut self) -> Result<Expression> {
         if let Some(t) = self.advance() {
+            let span = t.span();
+            // a group in parentheses is not a node on its own
+            if !matches!(t.ty(), TokenType::LPAREN) {
+                self.count_node(span)?;
+            }
             match t.get_type() {
-                TokenType::LITERAL(l) => Ok(Expression::Number(l)),
+                TokenType::LITERAL(l) => Ok(Expression::Number(l, span)),
                 TokenType::LPAREN => {
+                    self.depth += 1;
                     let expr = self.parse_expression(0)?;
-                    if let Some(j) = self.advance() {
-                        match j.ty() {
-                            TokenType::RPAREN => Ok(expr),
-                            _ => Err(Error::unexpected_value(")", Some(&format!("{:?}", j.ty())))),
-                        }
-                    } else {
-                        Err(Error::unexpected_value(")", None))
-                    }
+                    let rparen = self.expect_token(TokenType::RPAREN)?;
+                    self.depth -= 1;
+                    // the group covers the parentheses as well, there is no separate node for them
+                    let span = span.to(rparen.span());
+                    Ok(expr.with_span(span))
+                }
+                TokenType::IDENTIFIER(ident) if ident == "let" => self.parse_let(span),
+                TokenType::IDENTIFIER(ident) if is_keyword(&ident) => {
+                    Err(self.unexpected("number or (", Some(t)))
                 }
                 TokenType::IDENTIFIER(ident) => {
-                    if let Some(next) = self.peek() {
-                        if let TokenType::LPAREN = next.ty() {
-                            self.advance();
-                            let args = self.parse_argument_list()?;
-                            self.expect_token(TokenType::RPAREN)?;
-                            Ok(Expression::Call {
-                                callee: ident.clone(),
-                                args,
-                            })
-                        } else {
-                            Ok(Expression::Variable(ident.clone()))
-                        }
+                    if let Some(next) = self.peek()
+                        && let TokenType::LPAREN = next.ty()
+                        && self.is_function(&ident)
+                    {
+                        self.advance();
+                        self.depth += 1;
+                        let args = self.parse_argument_list()?;
+                        let rparen = self.expect_token(TokenType::RPAREN)?;
+                        self.depth -= 1;
+                        Ok(Expression::Call {
+                            callee: ident.clone(),
+                            args,
+                            span: span.to(rparen.span()),
+                        })
                     } else {
-                        Ok(Expression::Variable(ident.clone()))
+                        Ok(Expression::Variable(ident.clone(), span))
                     }
                 }
-                _ => Err(Error::unexpected_value("number or (", None)),
+                _ => Err(self.unexpected("number or (", Some(t))),
             }
         } else {
-            Err(Error::unexpected_value("token", None))
+            Err(self.unexpected("number or (", None))
+        }
+    }
+
+    /// Parses `name = value in body`, the `let` keyword is already consumed
+    fn parse_let(&mut self, let_span: Span) -> Result<Expression> {
+        let name = self.expect_name("variable name")?;
+        self.expect_token(TokenType::ASSIGN)?;
+        let value = self.parse_expression(0)?;
+
+        self.skip_newlines();
+        match self.advance() {
+            Some(t) if matches!(t.ty(), TokenType::IDENTIFIER(i) if i == "in") => {}
+            token => return Err(self.unexpected("in", token)),
+        }
+        // the body extends as far as possible, just like the else branch of a conditional
+        let body = self.parse_expression(0)?;
+
+        Ok(Expression::Let {
+            name,
+            span: let_span.to(body.span()),
+            value: Box::new(value),
+            body: Box::new(body),
+        })
+    }
+
+    /// Consumes an identifier which can be used as a name of a variable or a function
+    fn expect_name(&mut self, expected: &str) -> Result<String> {
+        match self.advance() {
+            Some(t) if matches!(t.ty(), TokenType::IDENTIFIER(i) if !is_keyword(i)) => {
+                Ok(t.ty().to_string())
+            }
+            token => Err(self.unexpected(expected, token)),
         }
     }
 
     fn parse_argument_list(&mut self) -> Result<Vec<Expression>> {
         let mut args = Vec::new();
 
-        if let Some(tok) = self.peek() {
-            if let TokenType::RPAREN = tok.ty() {
-                return Ok(args);
-            }
+        if let Some(tok) = self.peek()
+            && let TokenType::RPAREN = tok.ty()
+        {
+            return Ok(args);
         }
 
         loop {
@@ -111,43 +296,297 @@ impl<'a> Parser<'a> {
                     self.advance();
                 }
                 Some(TokenType::RPAREN) => break,
-                _ => return Err(Error::unexpected_value(", or ( in argument list", None)),
+                _ => return Err(self.unexpected(", or )", self.peek())),
             }
         }
 
         Ok(args)
     }
 
-    /// The main method, parses the whole slice of tokens into a nested expression... recursively.
+    fn is_function(&self, ident: &str) -> bool {
+        match &self.options.functions {
+            Some(functions) if self.options.implicit_multiplication => functions.contains(ident),
+            _ => true,
+        }
+    }
+
+    /// Checks if the next token starts a term implicitly multiplied by the previous one
+    fn is_implicit_operand(&self, next: &Token) -> bool {
+        if !self.options.implicit_multiplication || self.pos == 0 {
+            return false;
+        }
+
+        let prev = &self.tokens[self.pos - 1];
+        match prev.ty() {
+            TokenType::LITERAL(_) | TokenType::RPAREN => match next.ty() {
+                TokenType::IDENTIFIER(ident) => !is_keyword(ident),
+                TokenType::LITERAL(_) | TokenType::LPAREN => true,
+                _ => false,
+            },
+            // only a variable can get here, calls end with `)`
+            TokenType::IDENTIFIER(_) => matches!(next.ty(), TokenType::LPAREN),
+            _ => false,
+        }
+    }
+
+    /// Parses the `? then : otherwise` part of a conditional, the `?` is the current token
+    fn parse_conditional(&mut self, condition: Expression) -> Result<Expression> {
+        self.count_node(self.current_span())?;
+        self.advance();
+        let then = self.parse_expression(TERNARY_PRECEDENCE)?;
+        self.skip_newlines();
+        self.expect_token(TokenType::COLON)?;
+        // the same precedence on the right makes it right associative, `a ? b : c ? d : e`
+        // is `a ? b : (c ? d : e)`
+        let otherwise = self.parse_expression(TERNARY_PRECEDENCE)?;
+
+        Ok(Expression::Conditional {
+            span: condition.span().to(otherwise.span()),
+            condition: Box::new(condition),
+            then: Box::new(then),
+            otherwise: Box::new(otherwise),
+        })
+    }
+
+    /// Parses the whole slice of tokens into a single expression. Unlike
+    /// [`parse_expression`][`Parser::parse_expression`] it makes sure every parenthesis is
+    /// balanced and nothing is left after the expression, so `2 3` or `(2 + 2` are rejected.
+    pub fn parse(&mut self) -> Result<Expression> {
+        self.check_parens()?;
+
+        let expr = self.parse_expression(0)?;
+        self.skip_newlines();
+        if let Some(token) = self.peek() {
+            return Err(self.unexpected("end of input", Some(token)));
+        }
+
+        Ok(expr)
+    }
+
+    /// Checks if the statement at the current token looks like `name(...) = ...`
+    fn is_function_definition(&self) -> bool {
+        let mut tokens = self.tokens[self.pos.min(self.tokens.len())..]
+            .iter()
+            .map(|t| t.ty());
+        if !matches!(tokens.next(), Some(TokenType::IDENTIFIER(_)))
+            || !matches!(tokens.next(), Some(TokenType::LPAREN))
+        {
+            return false;
+        }
+
+        let mut depth = 1;
+        for ty in tokens.by_ref() {
+            match ty {
+                TokenType::LPAREN => depth += 1,
+                TokenType::RPAREN => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                break;
+            }
+        }
+
+        matches!(tokens.next(), Some(TokenType::ASSIGN))
+    }
+
+    fn parse_function_definition(&mut self) -> Result<Statement> {
+        let name_span = self.peek().map(|t| t.span()).unwrap_or_default();
+        let name = self.expect_name("function name")?;
+        self.expect_token(TokenType::LPAREN)?;
+        self.depth += 1;
+
+        let mut params: Vec<String> = Vec::new();
+        if !matches!(self.peek().map(|t| t.ty()), Some(TokenType::RPAREN)) {
+            loop {
+                let param_token = self.peek();
+                let param = self.expect_name("parameter name")?;
+                if params.contains(&param) {
+                    return Err(self.unexpected("unique parameter name", param_token));
+                }
+                params.push(param);
+
+                match self.peek().map(|t| t.ty()) {
+                    Some(TokenType::COMMA) => {
+                        self.advance();
+                    }
+                    _ => break,
+                }
+            }
+        }
+
+        self.expect_token(TokenType::RPAREN)?;
+        self.depth -= 1;
+        self.expect_token(TokenType::ASSIGN)?;
+        let body = self.parse_expression(0)?;
+
+        Ok(Statement::Function {
+            name,
+            params,
+            span: name_span.to(body.span()),
+            body,
+        })
+    }
+
+    /// Parses the whole slice of tokens into a [`Program`], a list of statements separated by `;`
+    /// or line breaks. A statement is an assignment like `rate = 0.07` or a plain expression.
+    /// A line break doesn't end a statement if it's inside of parentheses or right after an
+    /// operator, so long expressions can be split across lines.
+    ///
+    /// ## Example usage
+    /// ```
+    /// use lexper::{Interpreter, Lexer, Parser};
+    ///
+    /// let mut lexer = Lexer::new("rate = 0.07; principal = 1000\nprincipal * (1 + rate)^10".to_string());
+    /// lexer.lex().unwrap();
+    /// let program = Parser::new(lexer.tokens()).parse_program().unwrap();
+    ///
+    /// let mut interpreter = Interpreter::new();
+    /// let result = interpreter.run(&program).unwrap();
+    /// assert_eq!(result.round(), 1967.0);
+    /// ```
+    pub fn parse_program(&mut self) -> Result<Program> {
+        self.check_parens()?;
+        self.statements = true;
+
+        let mut statements = Vec::new();
+        loop {
+            while let Some(TokenType::SEMICOLON | TokenType::NEWLINE) = self.peek().map(|t| t.ty())
+            {
+                self.advance();
+            }
+            if self.peek().is_none() {
+                break;
+            }
+
+            statements.push(self.parse_statement()?);
+            match self.peek() {
+                None => break,
+                Some(t) if matches!(t.ty(), TokenType::SEMICOLON | TokenType::NEWLINE) => {}
+                token => return Err(self.unexpected("; or line break", token)),
+            }
+        }
+
+        if statements.is_empty() {
+            return Err(self.unexpected("statement", None));
+        }
+
+        Ok(Program { statements })
+    }
+
+    fn parse_statement(&mut self) -> Result<Statement> {
+        if self.is_function_definition() {
+            return self.parse_function_definition();
+        }
+
+        if let Some(name) = self.tokens.get(self.pos)
+            && let TokenType::IDENTIFIER(ident) = name.ty()
+            && !is_keyword(ident)
+            && let Some(TokenType::ASSIGN) = self.tokens.get(self.pos + 1).map(|t| t.ty())
+        {
+            self.pos += 2;
+            let value = self.parse_expression(0)?;
+            return Ok(Statement::Assign {
+                name: ident.clone(),
+                span: name.span().to(value.span()),
+                value,
+            });
+        }
+
+        Ok(Statement::Expression(self.parse_expression(0)?))
+    }
+
+    fn check_parens(&self) -> Result<()> {
+        let mut open = Vec::new();
+        for token in self.tokens {
+            match token.ty() {
+                TokenType::LPAREN => open.push(token.span()),
+                TokenType::RPAREN => {
+                    open.pop()
+                        .ok_or_else(|| Error::unmatched_paren(token.span()))?;
+                }
+                _ => {}
+            }
+        }
+
+        match open.pop() {
+            Some(span) => Err(Error::unclosed_paren(span)),
+            None => Ok(()),
+        }
+    }
+
+    /// Parses a single expression starting at the current token, stops at the first token that
+    /// can't continue it. Use [`parse`][`Parser::parse`] to parse the whole input.
     pub fn parse_expression(&mut self, min_prec: u8) -> Result<Expression> {
+        // every nested expression goes through here, so that's where the recursion is limited
+        if self.nesting >= self.options.limits.max_depth {
+            return Err(Error::nesting_too_deep(
+                self.options.limits.max_depth,
+                self.current_span(),
+            ));
+        }
+
+        self.nesting += 1;
+        let result = self.parse_binary(min_prec);
+        self.nesting -= 1;
+        result
+    }
+
+    fn parse_binary(&mut self, min_prec: u8) -> Result<Expression> {
         let mut lhs = self.parse_primary()?;
 
         while let Some(t) = self.peek() {
-            if let Some(op) = t.get_op() {
-                let prec = op.precendance();
-
-                if prec < min_prec {
+            if let TokenType::QUESTION = t.ty() {
+                if min_prec > TERNARY_PRECEDENCE {
                     break;
                 }
+                lhs = self.parse_conditional(lhs)?;
+                continue;
+            }
 
-                self.advance();
+            let (op, implicit) = if let Some(op) = t.get_op() {
+                (op, false)
+            } else if self.is_implicit_operand(t) {
+                (OperatorType::MULTIPLY, true)
+            } else {
+                break;
+            };
 
-                let rhs = self.parse_expression(prec + 1)?;
+            let prec = op.precedence();
 
-                lhs = Expression::Binary {
-                    left: Box::new(lhs),
-                    op,
-                    right: Box::new(rhs),
-                };
-            } else {
+            if prec < min_prec {
                 break;
             }
+
+            self.count_node(t.span())?;
+            // there is no token for an implicit multiplication, so nothing to skip
+            if !implicit {
+                self.advance();
+            }
+
+            // right associative operators let the right side contain the same operator again
+            let next_prec = match op.associativity() {
+                Associativity::Left => prec + 1,
+                Associativity::Right => prec,
+            };
+            let rhs = self.parse_expression(next_prec)?;
+
+            lhs = Expression::Binary {
+                span: lhs.span().to(rhs.span()),
+                left: Box::new(lhs),
+                op,
+                right: Box::new(rhs),
+            };
         }
 
         Ok(lhs)
     }
 }
 
+/// Identifiers with a special meaning, they can't be used as names
+fn is_keyword(ident: &str) -> bool {
+    matches!(ident, "let" | "in")
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -167,10 +606,348 @@ mod test {
         assert_eq!(
             ast,
             Expression::Binary {
-                left: Box::new(Expression::Number(2.0)),
+                left: Box::new(Expression::Number(2.0, Span::new(0, 1))),
                 op: OperatorType::PLUS,
-                right: Box::new(Expression::Number(3.0))
+                right: Box::new(Expression::Number(3.0, Span::new(4, 5))),
+                span: Span::new(0, 5),
             }
         )
     }
+
+    fn parse(raw: &str) -> Result<Expression> {
+        let mut lexer = crate::lexer::Lexer::new(raw.to_string());
+        lexer.lex()?;
+        Parser::new(lexer.tokens()).parse()
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed() {
+        assert!(parse("(2 + 2) * 3").is_ok());
+
+        let err = parse("2 3").unwrap_err();
+        assert!(matches!(err, Error::UnexpectedValue { span, .. } if span == Span::new(2, 3)));
+
+        let err = parse("(1 + (2 * 3)").unwrap_err();
+        assert!(matches!(err, Error::UnclosedParen { span } if span == Span::new(0, 1)));
+
+        let err = parse("max(1, 2").unwrap_err();
+        assert!(matches!(err, Error::UnclosedParen { span } if span == Span::new(3, 4)));
+
+        let err = parse("(1 + 2)) + 3").unwrap_err();
+        assert!(matches!(err, Error::UnmatchedParen { span } if span == Span::new(7, 8)));
+    }
+
+    #[test]
+    fn test_precedence_and_associativity() {
+        let cases = [
+            ("2^3^2", 512.0),
+            ("-2^2", -4.0),
+            ("2^-1", 0.5),
+            ("-2 * 3", -6.0),
+            ("6 / 4 % 3", 1.5),
+            ("10 % 3 * 2", 2.0),
+            ("10 - 4 - 3", 3.0),
+            ("2 + 3 * 4^2", 50.0),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(crate::eval(raw).unwrap(), expected, "{}", raw);
+        }
+
+        let ast = parse("2^3^2").unwrap();
+        let Expression::Binary { right, .. } = ast else {
+            panic!("expected binary expression, got {:?}", ast);
+        };
+        assert!(matches!(
+            *right,
+            Expression::Binary {
+                op: OperatorType::POWER,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_unary_operators() {
+        assert_eq!(crate::eval("+(-3)").unwrap(), -3.0);
+        assert_eq!(crate::eval("-(-3)").unwrap(), 3.0);
+        assert_eq!(crate::eval("+-+3").unwrap(), -3.0);
+
+        let ast = parse("-x").unwrap();
+        assert!(matches!(
+            ast,
+            Expression::Unary {
+                op: UnaryOp::MINUS,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_factorial() {
+        let cases = [
+            ("5!", 120.0),
+            ("0!", 1.0),
+            ("3!!", 720.0),
+            ("-3!", -6.0),
+            ("2^3!", 64.0),
+            ("2 * 3! + 1", 13.0),
+            ("(1 + 2)!", 6.0),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(crate::eval(raw).unwrap(), expected, "{}", raw);
+        }
+
+        let half = crate::eval("0.5!").unwrap();
+        assert!((half - std::f64::consts::PI.sqrt() / 2.0).abs() < 1e-12);
+
+        // past 170! the result overflows, it's infinite rather than NaN
+        for raw in ["200!", "1000!", "1000.5!"] {
+            assert_eq!(crate::eval(raw).unwrap(), f64::INFINITY, "{}", raw);
+        }
+        let big = crate::eval("150.5!").unwrap();
+        let bigger = crate::eval("151.5!").unwrap();
+        assert!(big.is_finite());
+        assert!((bigger / big - 151.5).abs() < 1e-9);
+
+        let err = crate::eval("(-2)!").unwrap_err();
+        assert!(matches!(err, Error::DomainError { span, .. } if span == Span::new(0, 5)));
+    }
+
+    #[test]
+    fn test_implicit_multiplication() {
+        let mut interpreter = crate::Interpreter::new();
+        interpreter.define_var("a".to_string(), 3.0);
+        interpreter.define_var("x".to_string(), 2.0);
+
+        let options = ParserOptions {
+            implicit_multiplication: true,
+            functions: Some(interpreter.function_names().map(String::from).collect()),
+            ..ParserOptions::default()
+        };
+        let eval = |raw: &str| {
+            let mut lexer = crate::lexer::Lexer::new(raw.to_string());
+            lexer.lex()?;
+            let expr = Parser::with_options(lexer.tokens(), options.clone()).parse()?;
+            interpreter.evaluate(expr)
+        };
+
+        let cases = [
+            ("2a", 6.0),
+            ("3(4 + 1)", 15.0),
+            ("(a + 1)(a - 1)", 8.0),
+            ("a(x)", 6.0),
+            ("2x^2", 8.0),
+            ("2 max(a, x)", 6.0),
+            ("1 + 2a * 2", 13.0),
+            ("-2a", -6.0),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(eval(raw).unwrap(), expected, "{}", raw);
+        }
+        assert_eq!(eval("2PI").unwrap(), 2.0 * std::f64::consts::PI);
+
+        // still a call, and the mode is off by default
+        assert!(eval("max(1)").is_err());
+        assert!(parse("2a").is_err());
+    }
+
+    #[test]
+    fn test_comparison_and_logic() {
+        let cases = [
+            ("3 > 2", 1.0),
+            ("3 >= 4", 0.0),
+            ("1 + 1 == 2", 1.0),
+            ("2 != 2", 0.0),
+            ("1 < 2 && 2 <= 2", 1.0),
+            ("0 || 3 < 2", 0.0),
+            ("1 || 0 && 0", 1.0),
+            ("!0", 1.0),
+            ("!5 == 0", 1.0),
+            ("!(1 < 2)", 0.0),
+            ("-1 < 0 == 1", 1.0),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(crate::eval(raw).unwrap(), expected, "{}", raw);
+        }
+
+        // the right side is never evaluated, so undefined identifiers there are fine
+        assert_eq!(crate::eval("0 && undefined").unwrap(), 0.0);
+        assert_eq!(crate::eval("1 || undefined(1)").unwrap(), 1.0);
+        assert!(crate::eval("1 && undefined").is_err());
+    }
+
+    #[test]
+    fn test_conditional() {
+        let cases = [
+            ("1 < 2 ? 10 : 20", 10.0),
+            ("0 ? 10 : 20", 20.0),
+            ("0 ? 1 : 0 ? 2 : 3", 3.0),
+            ("1 ? 0 ? 1 : 2 : 3", 2.0),
+            ("2 + (1 ? 3 : 4) * 2", 8.0),
+            ("1 || 0 ? 5 : 6", 5.0),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(crate::eval(raw).unwrap(), expected, "{}", raw);
+        }
+
+        assert_eq!(crate::eval("1 ? 2 : missing").unwrap(), 2.0);
+        assert!(parse("1 ? 2").is_err());
+
+        let ast = parse("0 ? 1 : 0 ? 2 : 3").unwrap();
+        let Expression::Conditional { otherwise, .. } = ast else {
+            panic!("expected conditional expression, got {:?}", ast);
+        };
+        assert!(matches!(*otherwise, Expression::Conditional { .. }));
+    }
+
+    fn parse_program(raw: &str) -> Result<Program> {
+        let mut lexer = crate::lexer::Lexer::new(raw.to_string());
+        lexer.lex()?;
+        Parser::new(lexer.tokens()).parse_program()
+    }
+
+    #[test]
+    fn test_program() {
+        let program = parse_program("a = 1; b = a +\n 2\n\n(a\n + b);").unwrap();
+        assert_eq!(program.statements.len(), 3);
+        assert!(matches!(&program.statements[0], Statement::Assign { name, .. } if name == "a"));
+        assert!(matches!(&program.statements[1], Statement::Assign { name, .. } if name == "b"));
+        assert!(matches!(
+            &program.statements[2],
+            Statement::Expression(Expression::Binary { .. })
+        ));
+
+        // a line break ends the statement, so this is `1` and `-2`, not `1 - 2`
+        assert_eq!(parse_program("1\n-2").unwrap().statements.len(), 2);
+
+        let program = parse_program("f(x, y) = x * y; g() = 1").unwrap();
+        assert!(matches!(
+            &program.statements[0],
+            Statement::Function { name, params, .. } if name == "f" && params == &["x", "y"]
+        ));
+        assert!(matches!(
+            &program.statements[1],
+            Statement::Function { params, .. } if params.is_empty()
+        ));
+        assert!(parse_program("f(x, x) = x").is_err());
+        assert!(parse_program("f(1) = 2").is_err());
+
+        assert!(parse_program("a = 1 b = 2").is_err());
+        assert!(parse_program("1 = 2").is_err());
+        assert!(parse_program(" ;\n").is_err());
+        assert!(parse("a = 1").is_err());
+        assert_eq!(crate::eval("\n2 *\n3\n").unwrap(), 6.0);
+        // outside of a program a line break is just whitespace
+        assert_eq!(crate::eval("2\n+3").unwrap(), 5.0);
+        assert_eq!(crate::eval("2\n* (1\n+ 2)\n!").unwrap(), 12.0);
+        assert!(crate::eval("2\n3").is_err());
+    }
+
+    #[test]
+    fn test_let() {
+        let ast = parse("let x = 1 + 2 in x * x").unwrap();
+        let Expression::Let {
+            name,
+            value,
+            body,
+            span,
+        } = ast
+        else {
+            panic!("expected let expression, got {:?}", ast);
+        };
+        assert_eq!(name, "x");
+        assert!(matches!(
+            *value,
+            Expression::Binary {
+                op: OperatorType::PLUS,
+                ..
+            }
+        ));
+        assert!(matches!(
+            *body,
+            Expression::Binary {
+                op: OperatorType::MULTIPLY,
+                ..
+            }
+        ));
+        assert_eq!(span, Span::new(0, 22));
+
+        assert_eq!(
+            crate::eval("let x = 3 in\nlet y = 4 in\nx^2 + y^2").unwrap(),
+            25.0
+        );
+        assert!(parse("let x = 1").is_err());
+        assert!(parse("let in = 1 in in").is_err());
+        assert!(parse("in + 1").is_err());
+        assert!(parse_program("let = 1").is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        let parse_with = |raw: &str, limits: Limits| {
+            let mut lexer = crate::lexer::Lexer::new(raw.to_string());
+            lexer.lex()?;
+            let options = ParserOptions {
+                limits,
+                ..ParserOptions::default()
+            };
+            Parser::with_options(lexer.tokens(), options).parse()
+        };
+
+        // deep nesting is an error instead of a stack overflow
+        let deep = format!("{}1{}", "(".repeat(100_000), ")".repeat(100_000));
+        let err = parse(&deep).unwrap_err();
+        assert!(
+            matches!(err, Error::NestingTooDeep { limit, .. } if limit == Limits::DEFAULT_MAX_DEPTH)
+        );
+        assert!(matches!(
+            parse(&format!("{}1", "-".repeat(1_000_000))).unwrap_err(),
+            Error::NestingTooDeep { .. }
+        ));
+        assert!(matches!(
+            parse(&"2^".repeat(100_000)).unwrap_err(),
+            Error::NestingTooDeep { .. }
+        ));
+        // long flat expressions don't nest while parsing
+        assert!(parse(&format!("{}1", "1 + ".repeat(10_000))).is_ok());
+        assert!(parse(&format!("1{}", "!".repeat(10_000))).is_ok());
+        assert_eq!(
+            crate::eval(&format!("{}1", "1 + ".repeat(999))).unwrap(),
+            1000.0
+        );
+
+        let limits = Limits {
+            max_depth: 3,
+            ..Limits::default()
+        };
+        assert!(parse_with("((1))", limits).is_ok());
+        let err = parse_with("(((1)))", limits).unwrap_err();
+        assert!(matches!(err, Error::NestingTooDeep { limit: 3, span } if span == Span::new(3, 4)));
+
+        let limits = Limits {
+            max_nodes: 5,
+            ..Limits::default()
+        };
+        // `1 + 2 * -x` has 6 nodes, the last one counted is `x`
+        assert!(parse_with("1 + 2 * x", limits).is_ok());
+        let err = parse_with("1 + 2 * -x", limits).unwrap_err();
+        assert!(matches!(err, Error::TooManyNodes { limit: 5, span } if span == Span::new(9, 10)));
+    }
+
+    #[test]
+    fn test_expr_spans() {
+        let raw = "-(1 + x) * max(2, 3)";
+        let mut lexer = crate::lexer::Lexer::new(raw.to_string());
+        lexer.lex().unwrap();
+
+        let mut parser = Parser::new(lexer.tokens());
+        let ast = parser.parse_expression(0).unwrap();
+        assert_eq!(ast.span(), Span::new(0, raw.len()));
+
+        let Expression::Binary { left, right, .. } = ast else {
+            panic!("expected binary expression, got {:?}", ast);
+        };
+        assert_eq!(&raw[left.span().start..left.span().end], "-(1 + x)");
+        assert_eq!(&raw[right.span().start..right.span().end], "max(2, 3)");
+    }
 }
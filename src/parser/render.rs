@@ -0,0 +1,665 @@
+use crate::lexer::{Associativity, OperatorType, PREFIX_PRECEDENCE, TERNARY_PRECEDENCE};
+
+use super::format::{ATOM_PRECEDENCE, POSTFIX_PRECEDENCE, number};
+use super::{Expression, PostfixOp, UnaryOp};
+
+/// Precedence of things laid out in two dimensions, fractions, powers and cases. They need
+/// parentheses only as a base of a power or before a postfix operator, like `(a/b)^2`.
+const LAYOUT_PRECEDENCE: u8 = 8;
+
+/// Markup languages [`Expression`]s are rendered to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Markup {
+    /// LaTeX math, without the surrounding `$`, see [`Expression::to_latex`]
+    Latex,
+    /// Presentation MathML, see [`Expression::to_mathml`]
+    MathMl,
+}
+
+/// Customises how names are rendered by [`Expression::to_latex_with`] and
+/// [`Expression::to_mathml_with`]. `None` means the default rendering, so only the names which
+/// need something special have to be handled.
+///
+/// ## Example usage
+/// ```
+/// use lexper::{IdentifierRenderer, Lexer, Markup, Parser};
+///
+/// struct Units;
+///
+/// impl IdentifierRenderer for Units {
+///     fn variable(&self, name: &str, markup: Markup) -> Option<String> {
+///         match (name, markup) {
+///             ("velocity", Markup::Latex) => Some(r"\vec{v}".to_string()),
+///             ("velocity", Markup::MathMl) => Some("<mover><mi>v</mi><mo>→</mo></mover>".to_string()),
+///             _ => None,
+///         }
+///     }
+/// }
+///
+/// let mut lexer = Lexer::new("velocity * t".to_string());
+/// lexer.lex().unwrap();
+/// let expr = Parser::new(lexer.tokens()).parse().unwrap();
+/// assert_eq!(expr.to_latex_with(&Units), r"\vec{v} \cdot t");
+/// ```
+pub trait IdentifierRenderer {
+    /// Renders the name of a variable
+    fn variable(&self, _name: &str, _markup: Markup) -> Option<String> {
+        None
+    }
+
+    /// Renders the name of a called function, the arguments are rendered as usual after it
+    fn function(&self, _name: &str, _markup: Markup) -> Option<String> {
+        None
+    }
+}
+
+impl<R: IdentifierRenderer + ?Sized> IdentifierRenderer for &R {
+    fn variable(&self, name: &str, markup: Markup) -> Option<String> {
+        (**self).variable(name, markup)
+    }
+
+    fn function(&self, name: &str, markup: Markup) -> Option<String> {
+        (**self).function(name, markup)
+    }
+}
+
+/// Renders every name the default way
+struct DefaultNames;
+
+impl IdentifierRenderer for DefaultNames {}
+
+impl Expression {
+    /// Renders the expression as LaTeX math. Division is written as `\frac{}{}`, powers as
+    /// superscripts, conditionals as `cases` and parentheses are added where the precedence
+    /// requires them. Known functions and Greek letters get their commands, `sin(x)` is
+    /// `\sin\left(x\right)` and `PI` or `alpha` are `\pi` and `\alpha`. The part of a name after
+    /// `_` is a subscript.
+    ///
+    /// ## Example usage
+    /// ```
+    /// use lexper::{Lexer, Parser};
+    ///
+    /// let mut lexer = Lexer::new("(x_1 + 1) / 2 * sin(PI * x)^2".to_string());
+    /// lexer.lex().unwrap();
+    /// let expr = Parser::new(lexer.tokens()).parse().unwrap();
+    /// assert_eq!(
+    ///     expr.to_latex(),
+    ///     r"\frac{x_{1} + 1}{2} \cdot \sin\left(\pi \cdot x\right)^{2}"
+    /// );
+    /// ```
+    pub fn to_latex(&self) -> String {
+        self.to_latex_with(&DefaultNames)
+    }
+
+    /// Like [`to_latex`][`Expression::to_latex`], but names are rendered by `names` first
+    pub fn to_latex_with<R: IdentifierRenderer + ?Sized>(&self, names: &R) -> String {
+        Renderer::new(Markup::Latex, &names).expr(self, TERNARY_PRECEDENCE)
+    }
+
+    /// Renders the expression as a presentation MathML `<math>` element, the same way
+    /// [`to_latex`][`Expression::to_latex`] does it
+    ///
+    /// ## Example usage
+    /// ```
+    /// use lexper::{Lexer, Parser};
+    ///
+    /// let mut lexer = Lexer::new("x^2 / 2".to_string());
+    /// lexer.lex().unwrap();
+    /// let expr = Parser::new(lexer.tokens()).parse().unwrap();
+    /// assert_eq!(
+    ///     expr.to_mathml(),
+    ///     r#"<math xmlns="http://www.w3.org/1998/Math/MathML"><mfrac><msup><mi>x</mi><mn>2</mn></msup><mn>2</mn></mfrac></math>"#
+    /// );
+    /// ```
+    pub fn to_mathml(&self) -> String {
+        self.to_mathml_with(&DefaultNames)
+    }
+
+    /// Like [`to_mathml`][`Expression::to_mathml`], but names are rendered by `names` first
+    pub fn to_mathml_with<R: IdentifierRenderer + ?Sized>(&self, names: &R) -> String {
+        let math = Renderer::new(Markup::MathMl, &names).expr(self, TERNARY_PRECEDENCE);
+        format!(r#"<math xmlns="http://www.w3.org/1998/Math/MathML">{math}</math>"#)
+    }
+
+    /// How tight the rendered expression binds, it's different from the text one, since
+    /// fractions and powers group their operands on their own
+    fn render_precedence(&self) -> u8 {
+        match self {
+            Self::Number(n, _) if n.is_sign_negative() && !n.is_nan() => PREFIX_PRECEDENCE,
+            // `2.5 \times 10^{-7}` is a product
+            Self::Number(n, _) if n.is_finite() && number(*n).contains('e') => {
+                OperatorType::MULTIPLY.precedence()
+            }
+            Self::Number(..) | Self::Variable(..) | Self::Call { .. } => ATOM_PRECEDENCE,
+            Self::Unary { .. } => PREFIX_PRECEDENCE,
+            Self::Postfix { .. } => POSTFIX_PRECEDENCE,
+            Self::Binary {
+                op: OperatorType::DIVIDE | OperatorType::POWER,
+                ..
+            }
+            | Self::Conditional { .. } => LAYOUT_PRECEDENCE,
+            Self::Binary { op, .. } => op.precedence(),
+            Self::Let { .. } => TERNARY_PRECEDENCE,
+        }
+    }
+}
+
+struct Renderer<'a> {
+    markup: Markup,
+    names: &'a dyn IdentifierRenderer,
+}
+
+impl<'a> Renderer<'a> {
+    fn new(markup: Markup, names: &'a dyn IdentifierRenderer) -> Self {
+        Self { markup, names }
+    }
+
+    /// Renders the expression, in parentheses if it binds looser than `min_prec`
+    fn expr(&self, expr: &Expression, min_prec: u8) -> String {
+        let rendered = self.node(expr);
+        if expr.render_precedence() < min_prec {
+            self.parens(&rendered)
+        } else {
+            rendered
+        }
+    }
+
+    fn node(&self, expr: &Expression) -> String {
+        match expr {
+            Expression::Number(n, _) => self.number(*n),
+            Expression::Variable(name, _) => self
+                .names
+                .variable(name, self.markup)
+                .unwrap_or_else(|| self.identifier(name)),
+            Expression::Unary { op, expr, .. } => {
+                // `-(-x)` reads better than `--x`
+                let operand = self.expr(expr, PREFIX_PRECEDENCE + 1);
+                let symbol = match (self.markup, op) {
+                    (_, UnaryOp::PLUS) => "+",
+                    (_, UnaryOp::MINUS) => "-",
+                    (Markup::Latex, UnaryOp::NOT) => r"\lnot ",
+                    (Markup::MathMl, UnaryOp::NOT) => "¬",
+                };
+                self.row(&[self.operator(symbol), operand])
+            }
+            Expression::Postfix { op, expr, .. } => {
+                let operand = self.expr(expr, POSTFIX_PRECEDENCE);
+                let symbol = match op {
+                    PostfixOp::FACTORIAL => "!",
+                };
+                self.row(&[operand, self.operator(symbol)])
+            }
+            Expression::Binary {
+                left, op, right, ..
+            } => self.binary(left, op, right),
+            Expression::Conditional {
+                condition,
+                then,
+                otherwise,
+                ..
+            } => {
+                let (condition, then, otherwise) = (
+                    self.expr(condition, 0),
+                    self.expr(then, 0),
+                    self.expr(otherwise, 0),
+                );
+                match self.markup {
+                    Markup::Latex => format!(
+                        r"\begin{{cases}} {then} & \text{{if }} {condition} \\ {otherwise} & \text{{otherwise}} \end{{cases}}"
+                    ),
+                    Markup::MathMl => format!(
+                        "<mrow><mo>{{</mo><mtable>\
+                        <mtr><mtd>{then}</mtd><mtd><mtext>if&#xA0;</mtext>{condition}</mtd></mtr>\
+                        <mtr><mtd>{otherwise}</mtd><mtd><mtext>otherwise</mtext></mtd></mtr>\
+                        </mtable></mrow>"
+                    ),
+                }
+            }
+            Expression::Let {
+                name, value, body, ..
+            } => {
+                let name = self
+                    .names
+                    .variable(name, self.markup)
+                    .unwrap_or_else(|| self.identifier(name));
+                let (value, body) = (self.expr(value, 0), self.expr(body, 0));
+                match self.markup {
+                    Markup::Latex => {
+                        format!(r"\text{{let }} {name} = {value} \text{{ in }} {body}")
+                    }
+                    Markup::MathMl => format!(
+                        "<mrow><mtext>let&#xA0;</mtext>{name}<mo>=</mo>{value}\
+                        <mtext>&#xA0;in&#xA0;</mtext>{body}</mrow>"
+                    ),
+                }
+            }
+            Expression::Call { callee, args, .. } => self.call(callee, args),
+        }
+    }
+
+    fn binary(&self, left: &Expression, op: &OperatorType, right: &Expression) -> String {
+        let symbol = match (self.markup, op) {
+            // laid out on their own
+            (Markup::Latex, OperatorType::DIVIDE) => {
+                let (num, den) = (self.expr(left, 0), self.expr(right, 0));
+                return format!(r"\frac{{{num}}}{{{den}}}");
+            }
+            (Markup::MathMl, OperatorType::DIVIDE) => {
+                let (num, den) = (self.expr(left, 0), self.expr(right, 0));
+                return format!("<mfrac>{num}{den}</mfrac>");
+            }
+            (_, OperatorType::POWER) => {
+                return self.power(
+                    &self.expr(left, LAYOUT_PRECEDENCE + 1),
+                    &self.expr(right, 0),
+                );
+            }
+            (_, OperatorType::PLUS) => "+",
+            (_, OperatorType::MINUS) => "-",
+            (Markup::Latex, OperatorType::MULTIPLY) => r"\cdot",
+            (Markup::MathMl, OperatorType::MULTIPLY) => "⋅",
+            (Markup::Latex, OperatorType::MODULO) => r"\bmod",
+            (Markup::MathMl, OperatorType::MODULO) => "mod",
+            (_, OperatorType::EQ) => "=",
+            (Markup::Latex, OperatorType::NE) => r"\neq",
+            (Markup::MathMl, OperatorType::NE) => "≠",
+            (Markup::Latex, OperatorType::LT) => "<",
+            (Markup::MathMl, OperatorType::LT) => "&lt;",
+            (Markup::Latex, OperatorType::LE) => r"\leq",
+            (Markup::MathMl, OperatorType::LE) => "≤",
+            (Markup::Latex, OperatorType::GT) => ">",
+            (Markup::MathMl, OperatorType::GT) => "&gt;",
+            (Markup::Latex, OperatorType::GE) => r"\geq",
+            (Markup::MathMl, OperatorType::GE) => "≥",
+            (Markup::Latex, OperatorType::AND) => r"\land",
+            (Markup::MathMl, OperatorType::AND) => "∧",
+            (Markup::Latex, OperatorType::OR) => r"\lor",
+            (Markup::MathMl, OperatorType::OR) => "∨",
+        };
+
+        let prec = op.precedence();
+        let (left_prec, mut right_prec) = match op.associativity() {
+            Associativity::Left => (prec, prec + 1),
+            Associativity::Right => (prec + 1, prec),
+        };
+        // `a - (-b)`, not `a - -b`
+        if prec >= OperatorType::PLUS.precedence() && right.render_precedence() == PREFIX_PRECEDENCE
+        {
+            right_prec = PREFIX_PRECEDENCE + 1;
+        }
+        let (left_rendered, right_rendered) =
+            (self.expr(left, left_prec), self.expr(right, right_prec));
+
+        // a number times a name is written next to it, like `2x` or `2\pi`
+        if *op == OperatorType::MULTIPLY
+            && let Expression::Number(n, _) = left
+            && left.render_precedence() == ATOM_PRECEDENCE
+            && n.is_finite()
+            && matches!(right, Expression::Variable(..) | Expression::Call { .. })
+        {
+            return match self.markup {
+                Markup::Latex => format!("{left_rendered} {right_rendered}"),
+                Markup::MathMl => {
+                    self.row(&[left_rendered, self.operator("&#x2062;"), right_rendered])
+                }
+            };
+        }
+
+        match self.markup {
+            Markup::Latex => format!("{left_rendered} {symbol} {right_rendered}"),
+            Markup::MathMl => self.row(&[left_rendered, self.operator(symbol), right_rendered]),
+        }
+    }
+
+    fn call(&self, callee: &str, args: &[Expression]) -> String {
+        let rendered: Vec<String> = args.iter().map(|arg| self.expr(arg, 0)).collect();
+        let name = match self.names.function(callee, self.markup) {
+            Some(name) => name,
+            None => {
+                if let [arg] = rendered.as_slice()
+                    && let Some(special) = self.special_call(callee, arg)
+                {
+                    return special;
+                }
+                self.function_name(callee)
+            }
+        };
+
+        match self.markup {
+            Markup::Latex => format!(r"{name}\left({}\right)", rendered.join(", ")),
+            Markup::MathMl => format!(
+                "<mrow>{name}<mo>&#x2061;</mo><mrow><mo>(</mo>{}<mo>)</mo></mrow></mrow>",
+                rendered.join("<mo>,</mo>")
+            ),
+        }
+    }
+
+    /// Renders a call of a built-in function which has its own notation, like a root
+    fn special_call(&self, callee: &str, arg: &str) -> Option<String> {
+        let rendered = match (self.markup, callee) {
+            (Markup::Latex, "cbrt") => format!(r"\sqrt[3]{{{arg}}}"),
+            (Markup::Latex, "floor") => format!(r"\left\lfloor {arg} \right\rfloor"),
+            (Markup::Latex, "ceil") => format!(r"\left\lceil {arg} \right\rceil"),
+            (Markup::MathMl, "cbrt") => format!("<mroot>{arg}<mn>3</mn></mroot>"),
+            (Markup::MathMl, "floor") => format!("<mrow><mo>⌊</mo>{arg}<mo>⌋</mo></mrow>"),
+            (Markup::MathMl, "ceil") => format!("<mrow><mo>⌈</mo>{arg}<mo>⌉</mo></mrow>"),
+            _ => return None,
+        };
+        Some(rendered)
+    }
+
+    fn function_name(&self, callee: &str) -> String {
+        let known = matches!(callee, "sin" | "cos" | "tan" | "ln" | "max" | "min");
+        match self.markup {
+            Markup::Latex if known => format!(r"\{callee}"),
+            Markup::Latex => format!(r"\operatorname{{{}}}", escape_latex(callee)),
+            Markup::MathMl => format!("<mi>{callee}</mi>"),
+        }
+    }
+
+    /// Renders a variable name, Greek letters get their symbols and the part after `_` is a
+    /// subscript
+    fn identifier(&self, name: &str) -> String {
+        let (base, subscript) = match name.split_once('_') {
+            Some((base, subscript)) if !base.is_empty() && !subscript.is_empty() => {
+                (base, Some(subscript))
+            }
+            _ => (name, None),
+        };
+
+        match self.markup {
+            Markup::Latex => {
+                let base = latex_name(base, r"\mathit");
+                match subscript {
+                    Some(subscript) => {
+                        format!("{base}_{{{}}}", latex_name(subscript, r"\mathrm"))
+                    }
+                    None => base,
+                }
+            }
+            Markup::MathMl => {
+                let base = format!("<mi>{}</mi>", greek(base).map_or(base, |(_, c)| c));
+                match subscript {
+                    Some(subscript) if subscript.chars().all(|c| c.is_ascii_digit()) => {
+                        format!("<msub>{base}<mn>{subscript}</mn></msub>")
+                    }
+                    Some(subscript) => {
+                        let subscript = greek(subscript).map_or(subscript, |(_, c)| c);
+                        format!("<msub>{base}<mi>{subscript}</mi></msub>")
+                    }
+                    None => base,
+                }
+            }
+        }
+    }
+
+    fn number(&self, n: f64) -> String {
+        if n.is_nan() {
+            return match self.markup {
+                Markup::Latex => r"\mathrm{NaN}".to_string(),
+                Markup::MathMl => "<mi>NaN</mi>".to_string(),
+            };
+        }
+        if n.is_sign_negative() {
+            return self.row(&[self.operator("-"), self.number(-n)]);
+        }
+        if n.is_infinite() {
+            return match self.markup {
+                Markup::Latex => r"\infty".to_string(),
+                Markup::MathMl => "<mi>∞</mi>".to_string(),
+            };
+        }
+
+        let text = number(n);
+        let Some((mantissa, exponent)) = text.split_once('e') else {
+            return match self.markup {
+                Markup::Latex => text,
+                Markup::MathMl => format!("<mn>{text}</mn>"),
+            };
+        };
+        match self.markup {
+            Markup::Latex => format!(r"{mantissa} \times 10^{{{exponent}}}"),
+            Markup::MathMl => {
+                let exponent = match exponent.strip_prefix('-') {
+                    Some(digits) => self.row(&[self.operator("-"), format!("<mn>{digits}</mn>")]),
+                    None => format!("<mn>{exponent}</mn>"),
+                };
+                let power = self.power("<mn>10</mn>", &exponent);
+                self.row(&[format!("<mn>{mantissa}</mn>"), self.operator("×"), power])
+            }
+        }
+    }
+
+    fn power(&self, base: &str, exponent: &str) -> String {
+        match self.markup {
+            Markup::Latex => format!("{base}^{{{exponent}}}"),
+            Markup::MathMl => format!("<msup>{base}{exponent}</msup>"),
+        }
+    }
+
+    fn parens(&self, inner: &str) -> String {
+        match self.markup {
+            Markup::Latex => format!(r"\left({inner}\right)"),
+            Markup::MathMl => format!("<mrow><mo>(</mo>{inner}<mo>)</mo></mrow>"),
+        }
+    }
+
+    fn operator(&self, symbol: &str) -> String {
+        match self.markup {
+            Markup::Latex => symbol.to_string(),
+            Markup::MathMl => format!("<mo>{symbol}</mo>"),
+        }
+    }
+
+    /// Puts the parts next to each other, they're a single node in MathML
+    fn row(&self, parts: &[String]) -> String {
+        match self.markup {
+            Markup::Latex => parts.concat(),
+            Markup::MathMl => format!("<mrow>{}</mrow>", parts.concat()),
+        }
+    }
+}
+
+/// Greek letters by their names, the LaTeX command is the name itself
+const GREEK: [(&str, &str); 34] = [
+    ("alpha", "α"),
+    ("beta", "β"),
+    ("gamma", "γ"),
+    ("delta", "δ"),
+    ("epsilon", "ε"),
+    ("zeta", "ζ"),
+    ("eta", "η"),
+    ("theta", "θ"),
+    ("iota", "ι"),
+    ("kappa", "κ"),
+    ("lambda", "λ"),
+    ("mu", "μ"),
+    ("nu", "ν"),
+    ("xi", "ξ"),
+    ("pi", "π"),
+    ("rho", "ρ"),
+    ("sigma", "σ"),
+    ("tau", "τ"),
+    ("upsilon", "υ"),
+    ("phi", "φ"),
+    ("chi", "χ"),
+    ("psi", "ψ"),
+    ("omega", "ω"),
+    ("Gamma", "Γ"),
+    ("Delta", "Δ"),
+    ("Theta", "Θ"),
+    ("Lambda", "Λ"),
+    ("Xi", "Ξ"),
+    ("Pi", "Π"),
+    ("Sigma", "Σ"),
+    ("Upsilon", "Υ"),
+    ("Phi", "Φ"),
+    ("Psi", "Ψ"),
+    ("Omega", "Ω"),
+];
+
+/// Looks up a Greek letter by its name, `PI` is the constant of the interpreter, so it's `pi`
+fn greek(name: &str) -> Option<(&'static str, &'static str)> {
+    let name = if name == "PI" { "pi" } else { name };
+    GREEK.iter().find(|(letter, _)| *letter == name).copied()
+}
+
+/// A Greek letter, a single letter as is, or a longer name in the given font
+fn latex_name(name: &str, font: &str) -> String {
+    if let Some((letter, _)) = greek(name) {
+        return format!(r"\{letter}");
+    }
+    if name.chars().count() == 1 || name.chars().all(|c| c.is_ascii_digit()) {
+        return name.to_string();
+    }
+    format!("{font}{{{}}}", escape_latex(name))
+}
+
+/// Names have nothing special to LaTeX but `_`
+fn escape_latex(name: &str) -> String {
+    name.replace('_', r"\_")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Result;
+
+    fn parse(raw: &str) -> Result<Expression> {
+        let mut lexer = crate::Lexer::new(raw.to_string());
+        lexer.lex()?;
+        crate::Parser::new(lexer.tokens()).parse()
+    }
+
+    #[test]
+    fn test_latex() {
+        let cases = [
+            ("1 / (x + 1)", r"\frac{1}{x + 1}"),
+            (
+                "(a / b) ^ 2 + a ^ (b + 1)",
+                r"\left(\frac{a}{b}\right)^{2} + a^{b + 1}",
+            ),
+            (
+                "(a ^ b) ^ c - (-x) ^ 2",
+                r"\left(a^{b}\right)^{c} - \left(-x\right)^{2}",
+            ),
+            (
+                "a - (b - c) - -d",
+                r"a - \left(b - c\right) - \left(-d\right)",
+            ),
+            (
+                "2 * PI * r + 3 * (x + 1)",
+                r"2 \pi \cdot r + 3 \cdot \left(x + 1\right)",
+            ),
+            (
+                "sin(theta)^2 + cos(2 * x)",
+                r"\sin\left(\theta\right)^{2} + \cos\left(2 x\right)",
+            ),
+            (
+                "ceil(x) + cbrt(y) + floor(z)",
+                r"\left\lceil x \right\rceil + \sqrt[3]{y} + \left\lfloor z \right\rfloor",
+            ),
+            // not built in
+            (
+                "sqrt(x) * exp(ln(x))",
+                r"\operatorname{sqrt}\left(x\right) \cdot \operatorname{exp}\left(\ln\left(x\right)\right)",
+            ),
+            ("f(x, y) % 2", r"\operatorname{f}\left(x, y\right) \bmod 2"),
+            (
+                "rate_max * v_1 * Delta",
+                r"\mathit{rate}_{\mathrm{max}} \cdot v_{1} \cdot \Delta",
+            ),
+            (
+                "!(a <= b) && c != d || e",
+                r"\lnot \left(a \leq b\right) \land c \neq d \lor e",
+            ),
+            (
+                "(-x)! + --x",
+                r"\left(-x\right)! + \left(-\left(-x\right)\right)",
+            ),
+            (
+                "x > 0 ? x : -x",
+                r"\begin{cases} x & \text{if } x > 0 \\ -x & \text{otherwise} \end{cases}",
+            ),
+            (
+                "let a = 2 in a * a",
+                r"\text{let } a = 2 \text{ in } a \cdot a",
+            ),
+            ("1e21 + 2.5e-7", r"1 \times 10^{21} + 2.5 \times 10^{-7}"),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(parse(raw).unwrap().to_latex(), expected, "{raw}");
+        }
+
+        let infinity = Expression::Number(f64::NEG_INFINITY, Default::default());
+        assert_eq!(infinity.to_latex(), r"-\infty");
+    }
+
+    #[test]
+    fn test_mathml() {
+        let wrap = |math: &str| {
+            format!(r#"<math xmlns="http://www.w3.org/1998/Math/MathML">{math}</math>"#)
+        };
+        let cases = [
+            (
+                "2 * x - y_1",
+                "<mrow><mrow><mn>2</mn><mo>&#x2062;</mo><mi>x</mi></mrow><mo>-</mo>\
+                <msub><mi>y</mi><mn>1</mn></msub></mrow>",
+            ),
+            (
+                "(a + b) ^ 2 <= PI",
+                "<mrow><msup><mrow><mo>(</mo><mrow><mi>a</mi><mo>+</mo><mi>b</mi></mrow><mo>)</mo></mrow>\
+                <mn>2</mn></msup><mo>≤</mo><mi>π</mi></mrow>",
+            ),
+            (
+                "max(1, cbrt(x))",
+                "<mrow><mi>max</mi><mo>&#x2061;</mo><mrow><mo>(</mo><mn>1</mn><mo>,</mo>\
+                <mroot><mi>x</mi><mn>3</mn></mroot><mo>)</mo></mrow></mrow>",
+            ),
+            (
+                "-x!",
+                "<mrow><mo>-</mo><mrow><mi>x</mi><mo>!</mo></mrow></mrow>",
+            ),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(parse(raw).unwrap().to_mathml(), wrap(expected), "{raw}");
+        }
+    }
+
+    #[test]
+    fn test_identifier_renderer() {
+        struct Names;
+
+        impl IdentifierRenderer for Names {
+            fn variable(&self, name: &str, markup: Markup) -> Option<String> {
+                match (name, markup) {
+                    ("speed", Markup::Latex) => Some("v".to_string()),
+                    ("speed", Markup::MathMl) => Some("<mi>v</mi>".to_string()),
+                    _ => None,
+                }
+            }
+
+            fn function(&self, name: &str, markup: Markup) -> Option<String> {
+                match (name, markup) {
+                    ("cbrt", Markup::Latex) => Some(r"\operatorname{root}".to_string()),
+                    _ => None,
+                }
+            }
+        }
+
+        let expr = parse("cbrt(speed * t)").unwrap();
+        assert_eq!(
+            expr.to_latex_with(&Names),
+            r"\operatorname{root}\left(v \cdot t\right)"
+        );
+        assert!(
+            expr.to_mathml_with(&Names)
+                .contains("<mroot><mrow><mi>v</mi><mo>⋅</mo><mi>t</mi></mrow><mn>3</mn></mroot>")
+        );
+
+        // picked at runtime
+        let names: &dyn IdentifierRenderer = &Names;
+        assert_eq!(expr.to_latex_with(names), expr.to_latex_with(&Names));
+        assert_eq!(expr.to_mathml_with(names), expr.to_mathml_with(&Names));
+    }
+}
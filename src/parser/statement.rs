@@ -0,0 +1,70 @@
+use crate::{Expression, lexer::Span};
+
+/// A single statement of a [`Program`], an assignment, a function definition or a plain
+/// expression. Like with [`Expression`], spans are ignored when comparing statements.
+#[derive(Debug, Clone)]
+pub enum Statement {
+    /// An expression evaluated for its value -> "2 + 3"
+    Expression(Expression),
+
+    /// Evaluates the value and stores it into a variable -> "rate = 0.07"
+    Assign {
+        name: String,
+        value: Expression,
+        span: Span,
+    },
+
+    /// Defines a function in expression syntax -> "f(x, y) = x^2 + y"
+    Function {
+        name: String,
+        params: Vec<String>,
+        body: Expression,
+        span: Span,
+    },
+}
+
+impl PartialEq for Statement {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Expression(a), Self::Expression(b)) => a == b,
+            (
+                Self::Assign { name, value, .. },
+                Self::Assign {
+                    name: other_name,
+                    value: other_value,
+                    ..
+                },
+            ) => name == other_name && value == other_value,
+            (
+                Self::Function {
+                    name, params, body, ..
+                },
+                Self::Function {
+                    name: other_name,
+                    params: other_params,
+                    body: other_body,
+                    ..
+                },
+            ) => name == other_name && params == other_params && body == other_body,
+            _ => false,
+        }
+    }
+}
+
+impl Statement {
+    /// Returns the byte range of the source this statement was parsed from
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Expression(expr) => expr.span(),
+            Self::Assign { span, .. } | Self::Function { span, .. } => *span,
+        }
+    }
+}
+
+/// A list of statements separated by `;` or line breaks, see
+/// [`Parser::parse_program`][`crate::Parser::parse_program`] and
+/// [`Interpreter::run`][`crate::Interpreter::run`].
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Program {
+    pub statements: Vec<Statement>,
+}
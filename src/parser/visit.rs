@@ -0,0 +1,265 @@
+use std::ops::ControlFlow;
+
+use crate::lexer::{OperatorType, Span};
+
+use super::{Expression, PostfixOp, UnaryOp};
+
+/// What the methods of a [`Visitor`] return. The default methods walk the children one by one
+/// and stop as soon as one of them [`stops`][`VisitOutput::stops`], like `?` does.
+pub trait VisitOutput {
+    /// Returned by the default methods when the walk went through, and by the leaves
+    fn proceed() -> Self;
+
+    /// Checks if the walk has to stop and return this one right away
+    fn stops(&self) -> bool;
+}
+
+/// Visitors which only collect something into themselves never stop
+impl VisitOutput for () {
+    fn proceed() -> Self {}
+
+    fn stops(&self) -> bool {
+        false
+    }
+}
+
+/// Stops at the first [`ControlFlow::Break`], handy for searches
+impl<B> VisitOutput for ControlFlow<B> {
+    fn proceed() -> Self {
+        ControlFlow::Continue(())
+    }
+
+    fn stops(&self) -> bool {
+        self.is_break()
+    }
+}
+
+/// Stops at the first error, the default methods give `Ok(T::default())`, mind the values
+/// computed by a [`Visitor`]
+impl<T: Default, E> VisitOutput for Result<T, E> {
+    fn proceed() -> Self {
+        Ok(T::default())
+    }
+
+    fn stops(&self) -> bool {
+        self.is_err()
+    }
+}
+
+/// Walks an [`Expression`] by reference. Every method walks the children of its node by
+/// default, so a pass overrides only the nodes it's interested in.
+///
+/// The defaults return what [`VisitOutput::proceed`] gives, they know nothing of the value a
+/// visitor computes. A visitor returning a value for every node, like `Result<f64>`, has to
+/// override every method, otherwise the missed nodes evaluate to `Ok(0.0)` without an error.
+///
+/// ## Example usage
+/// ```
+/// use lexper::{Lexer, Parser, Span, Visitor};
+///
+/// /// Counts the uses of every variable
+/// #[derive(Default)]
+/// struct Uses<'a>(Vec<&'a str>);
+///
+/// impl<'a> Visitor<'a> for Uses<'a> {
+///     type Output = ();
+///
+///     fn visit_variable(&mut self, name: &'a str, _span: Span) {
+///         self.0.push(name);
+///     }
+/// }
+///
+/// let mut lexer = Lexer::new("x^2 + sin(y) * x".to_string());
+/// lexer.lex().unwrap();
+/// let expr = Parser::new(lexer.tokens()).parse().unwrap();
+///
+/// let mut uses = Uses::default();
+/// uses.visit_expression(&expr);
+/// assert_eq!(uses.0, ["x", "y", "x"]);
+/// ```
+pub trait Visitor<'a> {
+    type Output: VisitOutput;
+
+    /// Visits any expression, calls the method of its node through [`visit_node`]. Override it
+    /// to do something around every node.
+    fn visit_expression(&mut self, expr: &'a Expression) -> Self::Output {
+        visit_node(self, expr)
+    }
+
+    fn visit_number(&mut self, _value: f64, _span: Span) -> Self::Output {
+        Self::Output::proceed()
+    }
+
+    fn visit_variable(&mut self, _name: &'a str, _span: Span) -> Self::Output {
+        Self::Output::proceed()
+    }
+
+    fn visit_unary(&mut self, _op: UnaryOp, expr: &'a Expression, _span: Span) -> Self::Output {
+        visit_all(self, [expr])
+    }
+
+    fn visit_postfix(&mut self, _op: PostfixOp, expr: &'a Expression, _span: Span) -> Self::Output {
+        visit_all(self, [expr])
+    }
+
+    fn visit_binary(
+        &mut self,
+        left: &'a Expression,
+        _op: &'a OperatorType,
+        right: &'a Expression,
+        _span: Span,
+    ) -> Self::Output {
+        visit_all(self, [left, right])
+    }
+
+    fn visit_conditional(
+        &mut self,
+        condition: &'a Expression,
+        then: &'a Expression,
+        otherwise: &'a Expression,
+        _span: Span,
+    ) -> Self::Output {
+        visit_all(self, [condition, then, otherwise])
+    }
+
+    /// The binding is visited before the body
+    fn visit_let(
+        &mut self,
+        _name: &'a str,
+        value: &'a Expression,
+        body: &'a Expression,
+        _span: Span,
+    ) -> Self::Output {
+        visit_all(self, [value, body])
+    }
+
+    fn visit_call(
+        &mut self,
+        _callee: &'a str,
+        args: &'a [Expression],
+        _span: Span,
+    ) -> Self::Output {
+        visit_all(self, args)
+    }
+}
+
+/// Calls the method of the visitor matching the node of the expression, it's what
+/// [`Visitor::visit_expression`] does by default
+pub fn visit_node<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, expr: &'a Expression) -> V::Output {
+    match expr {
+        Expression::Number(value, span) => visitor.visit_number(*value, *span),
+        Expression::Variable(name, span) => visitor.visit_variable(name, *span),
+        Expression::Unary { op, expr, span } => visitor.visit_unary(*op, expr, *span),
+        Expression::Postfix { op, expr, span } => visitor.visit_postfix(*op, expr, *span),
+        Expression::Binary {
+            left,
+            op,
+            right,
+            span,
+        } => visitor.visit_binary(left, op, right, *span),
+        Expression::Conditional {
+            condition,
+            then,
+            otherwise,
+            span,
+        } => visitor.visit_conditional(condition, then, otherwise, *span),
+        Expression::Let {
+            name,
+            value,
+            body,
+            span,
+        } => visitor.visit_let(name, value, body, *span),
+        Expression::Call { callee, args, span } => visitor.visit_call(callee, args, *span),
+    }
+}
+
+/// Visits the expressions in order, until one of them stops the walk. It's what the default
+/// methods of [`Visitor`] do with the children, so an overriding method can walk them the same
+/// way after doing its part.
+pub fn visit_all<'a, V: Visitor<'a> + ?Sized>(
+    visitor: &mut V,
+    exprs: impl IntoIterator<Item = &'a Expression>,
+) -> V::Output {
+    for expr in exprs {
+        let output = visitor.visit_expression(expr);
+        if output.stops() {
+            return output;
+        }
+    }
+    V::Output::proceed()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Result;
+
+    fn parse(raw: &str) -> Result<Expression> {
+        let mut lexer = crate::Lexer::new(raw.to_string());
+        lexer.lex()?;
+        crate::Parser::new(lexer.tokens()).parse()
+    }
+
+    #[test]
+    fn test_visitor_walks_everything() {
+        #[derive(Default)]
+        struct Count {
+            nodes: usize,
+            calls: Vec<String>,
+        }
+
+        impl<'a> Visitor<'a> for Count {
+            type Output = ();
+
+            fn visit_expression(&mut self, expr: &'a Expression) {
+                self.nodes += 1;
+                visit_node(self, expr)
+            }
+
+            fn visit_call(&mut self, callee: &'a str, args: &'a [Expression], _span: Span) {
+                self.calls.push(callee.to_string());
+                visit_all(self, args)
+            }
+        }
+
+        let expr = parse("let a = max(1, -x) in a > 0 ? sqrt(a)! : f()").unwrap();
+        let mut count = Count::default();
+        count.visit_expression(&expr);
+        assert_eq!(count.nodes, 13);
+        assert_eq!(count.calls, ["max", "sqrt", "f"]);
+    }
+
+    #[test]
+    fn test_visitor_stops() {
+        /// Finds the first division by a literal zero
+        struct DivisionByZero;
+
+        impl<'a> Visitor<'a> for DivisionByZero {
+            type Output = ControlFlow<Span>;
+
+            fn visit_binary(
+                &mut self,
+                left: &'a Expression,
+                op: &'a OperatorType,
+                right: &'a Expression,
+                span: Span,
+            ) -> ControlFlow<Span> {
+                if *op == OperatorType::DIVIDE && matches!(right, Expression::Number(0.0, _)) {
+                    return ControlFlow::Break(span);
+                }
+                visit_all(self, [left, right])
+            }
+        }
+
+        let expr = parse("1 + (2 / 0) * (3 / 0)").unwrap();
+        assert_eq!(
+            DivisionByZero.visit_expression(&expr),
+            ControlFlow::Break(Span::new(4, 11))
+        );
+        let expr = parse("1 / (0 + 1)").unwrap();
+        assert_eq!(
+            DivisionByZero.visit_expression(&expr),
+            ControlFlow::Continue(())
+        );
+    }
+}
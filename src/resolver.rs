@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+
+use crate::{Error, Result, interpreter::LexpFn};
+
+/// Source of variable values for the [`Interpreter`][`crate::Interpreter`]. It's asked only for
+/// the identifiers an expression actually evaluates, right when they are needed, so values can
+/// be fetched lazily from a database row, a metrics store etc.
+///
+/// The resolver is consulted after the variables defined with
+/// [`define_var`][`crate::Interpreter::define_var`] and before the parent interpreter.
+/// Lookups take `&self`, use a `Cell` or a `RefCell` to cache fetched values.
+///
+/// ## Example usage
+/// ```
+/// use lexper::{Interpreter, Lexer, Parser, VariableResolver, error::Result};
+///
+/// struct Row;
+///
+/// impl VariableResolver for Row {
+///     fn resolve(&self, name: &str) -> Option<Result<f64>> {
+///         match name {
+///             "price" => Some(Ok(10.0)),
+///             "amount" => Some(Ok(3.0)),
+///             _ => None,
+///         }
+///     }
+/// }
+///
+/// let mut interpreter = Interpreter::new();
+/// interpreter.set_variable_resolver(Row);
+///
+/// let mut lexer = Lexer::new("price * amount".to_string());
+/// lexer.lex().unwrap();
+/// let expr = Parser::new(lexer.tokens()).parse().unwrap();
+/// assert_eq!(interpreter.evaluate(expr).unwrap(), 30.0);
+/// ```
+pub trait VariableResolver {
+    /// Returns the value of the variable, `None` if this resolver doesn't know it
+    fn resolve(&self, name: &str) -> Option<Result<f64>>;
+}
+
+/// Source of functions for the [`Interpreter`][`crate::Interpreter`], consulted for calls of
+/// functions which are not defined on the interpreter itself.
+///
+/// Functions are looked up in the same order as variables, the functions defined on the
+/// interpreter come first, then its resolver, then the parent interpreter. The resolver is asked
+/// whether it [`has`][`FunctionResolver::has`] the function before the arguments are evaluated.
+///
+/// ## Example usage
+/// ```
+/// use lexper::{FunctionResolver, Interpreter, Lexer, Parser, error::Result};
+///
+/// struct Units;
+///
+/// impl FunctionResolver for Units {
+///     fn has(&self, name: &str) -> bool {
+///         name == "km"
+///     }
+///
+///     fn call(&self, _name: &str, args: &[f64]) -> Result<f64> {
+///         Ok(args[0] * 1000.0)
+///     }
+/// }
+///
+/// let mut interpreter = Interpreter::new();
+/// interpreter.set_function_resolver(Units);
+///
+/// let mut lexer = Lexer::new("km(1.5) + 1".to_string());
+/// lexer.lex().unwrap();
+/// let expr = Parser::new(lexer.tokens()).parse().unwrap();
+/// assert_eq!(interpreter.evaluate(expr).unwrap(), 1501.0);
+/// ```
+pub trait FunctionResolver {
+    /// Checks if this resolver knows the function
+    fn has(&self, name: &str) -> bool;
+
+    /// Calls the function with already evaluated arguments, it's called only for the names
+    /// this resolver [`has`][`FunctionResolver::has`]
+    fn call(&self, name: &str, args: &[f64]) -> Result<f64>;
+}
+
+impl VariableResolver for HashMap<String, f64> {
+    fn resolve(&self, name: &str) -> Option<Result<f64>> {
+        self.get(name).copied().map(Ok)
+    }
+}
+
+impl FunctionResolver for HashMap<String, LexpFn> {
+    fn has(&self, name: &str) -> bool {
+        self.contains_key(name)
+    }
+
+    fn call(&self, name: &str, args: &[f64]) -> Result<f64> {
+        match self.get(name) {
+            Some(func) => func(args),
+            None => Err(Error::other(format!("Unknown function: {name}"))),
+        }
+    }
+}